@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS channel_settings (
+    uuid VARCHAR(36) NOT NULL PRIMARY KEY,
+    song_template VARCHAR(255) NULL
+);
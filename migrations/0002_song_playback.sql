@@ -0,0 +1,6 @@
+-- updated_at is set by the backend when a song is stored, in unix milliseconds
+ALTER TABLE song_data
+    ADD COLUMN duration_ms BIGINT NULL,
+    ADD COLUMN position_ms BIGINT NULL,
+    ADD COLUMN is_playing TINYINT(1) NULL,
+    ADD COLUMN updated_at BIGINT NULL;
@@ -0,0 +1,7 @@
+ALTER TABLE channel_settings
+    ADD COLUMN overlay_layout VARCHAR(16) NULL,
+    ADD COLUMN overlay_animation VARCHAR(16) NULL,
+    ADD COLUMN overlay_font VARCHAR(64) NULL,
+    ADD COLUMN overlay_text_color VARCHAR(9) NULL,
+    ADD COLUMN overlay_background_color VARCHAR(9) NULL,
+    ADD COLUMN overlay_accent_color VARCHAR(9) NULL;
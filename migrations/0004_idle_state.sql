@@ -0,0 +1,3 @@
+ALTER TABLE channel_settings
+    ADD COLUMN idle_text VARCHAR(255) NULL,
+    ADD COLUMN stale_after_secs INT NULL;
@@ -0,0 +1,6 @@
+ALTER TABLE songify_queue ADD COLUMN Position INT NULL;
+
+-- Existing items keep their current (insertion) order
+UPDATE songify_queue SET Position = Queueid WHERE Position IS NULL;
+
+CREATE INDEX idx_songify_queue_order ON songify_queue (Uuid, Played, Position);
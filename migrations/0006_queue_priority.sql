@@ -0,0 +1,4 @@
+ALTER TABLE songify_queue ADD COLUMN Priority INT NOT NULL DEFAULT 0;
+
+DROP INDEX idx_songify_queue_order ON songify_queue;
+CREATE INDEX idx_songify_queue_order ON songify_queue (Uuid, Played, Priority, Position);
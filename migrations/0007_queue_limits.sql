@@ -0,0 +1,3 @@
+ALTER TABLE channel_settings
+    ADD COLUMN max_requests_per_user INT NULL,
+    ADD COLUMN max_queue_length INT NULL;
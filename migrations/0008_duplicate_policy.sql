@@ -0,0 +1,3 @@
+ALTER TABLE channel_settings
+    ADD COLUMN reject_queued_duplicates TINYINT(1) NULL,
+    ADD COLUMN replay_cooldown_mins INT NULL;
@@ -0,0 +1,4 @@
+-- Length stays as sent by the client, LengthMs is the parsed value
+ALTER TABLE songify_queue ADD COLUMN LengthMs BIGINT NULL;
+
+ALTER TABLE channel_settings ADD COLUMN max_song_length_secs INT NULL;
@@ -0,0 +1,19 @@
+-- Played is kept in sync for older clients, Status says why an item left the queue
+ALTER TABLE songify_queue ADD COLUMN Status VARCHAR(16) NOT NULL DEFAULT 'queued';
+ALTER TABLE songify_queue ADD COLUMN StatusAt BIGINT NULL;
+ALTER TABLE songify_queue ADD COLUMN StatusBy VARCHAR(64) NULL;
+
+UPDATE songify_queue SET Status = 'played' WHERE Played = 1;
+
+CREATE INDEX songify_queue_status_idx ON songify_queue (Uuid, Status, StatusAt);
+
+CREATE TABLE IF NOT EXISTS songify_queue_status (
+    Id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    Queueid INT NOT NULL,
+    Uuid VARCHAR(64) NOT NULL,
+    Status VARCHAR(16) NOT NULL,
+    Actor VARCHAR(64) NOT NULL,
+    CreatedAt BIGINT NOT NULL,
+    INDEX songify_queue_status_queueid_idx (Queueid),
+    INDEX songify_queue_status_uuid_idx (Uuid, CreatedAt)
+);
@@ -0,0 +1,13 @@
+-- Every clear is a batch that can be restored for a short while
+CREATE TABLE IF NOT EXISTS songify_queue_clears (
+    Id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    Uuid VARCHAR(64) NOT NULL,
+    Actor VARCHAR(64) NOT NULL,
+    CreatedAt BIGINT NOT NULL,
+    RestoredAt BIGINT NULL,
+    RestoredBy VARCHAR(64) NULL,
+    INDEX songify_queue_clears_uuid_idx (Uuid, CreatedAt)
+);
+
+ALTER TABLE songify_queue ADD COLUMN ClearId BIGINT UNSIGNED NULL;
+CREATE INDEX songify_queue_clear_idx ON songify_queue (ClearId);
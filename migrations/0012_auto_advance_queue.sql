@@ -0,0 +1,2 @@
+-- Opt-in: marking the queue item played when it shows up as the current song
+ALTER TABLE channel_settings ADD COLUMN auto_advance_queue TINYINT(1) NULL;
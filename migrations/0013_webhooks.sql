@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS songify_webhooks (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    uuid VARCHAR(64) NOT NULL,
+    url VARCHAR(2048) NOT NULL,
+    secret VARCHAR(128) NOT NULL,
+    -- Comma separated event names, NULL subscribes to everything
+    events VARCHAR(255) NULL,
+    active TINYINT(1) NOT NULL DEFAULT 1,
+    created_at BIGINT NOT NULL,
+    INDEX songify_webhooks_uuid_idx (uuid)
+);
+
+-- One row per attempt, so retries show up individually
+CREATE TABLE IF NOT EXISTS songify_webhook_deliveries (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    webhook_id BIGINT UNSIGNED NOT NULL,
+    uuid VARCHAR(64) NOT NULL,
+    event VARCHAR(32) NOT NULL,
+    payload TEXT NOT NULL,
+    attempt INT NOT NULL,
+    status_code INT NULL,
+    error TEXT NULL,
+    success TINYINT(1) NOT NULL,
+    created_at BIGINT NOT NULL,
+    INDEX songify_webhook_deliveries_uuid_idx (uuid, id),
+    INDEX songify_webhook_deliveries_webhook_idx (webhook_id)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS songify_blocklist (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    uuid VARCHAR(64) NOT NULL,
+    -- track, artist or title
+    kind VARCHAR(16) NOT NULL,
+    value VARCHAR(255) NOT NULL,
+    reason VARCHAR(255) NULL,
+    created_at BIGINT NOT NULL,
+    UNIQUE KEY songify_blocklist_entry_idx (uuid, kind, value)
+);
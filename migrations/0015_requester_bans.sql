@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS songify_requester_bans (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    uuid VARCHAR(64) NOT NULL,
+    requester VARCHAR(255) NOT NULL,
+    reason VARCHAR(255) NULL,
+    banned_by VARCHAR(64) NOT NULL,
+    expires_at BIGINT NULL,
+    created_at BIGINT NOT NULL,
+    UNIQUE KEY songify_requester_bans_idx (uuid, requester)
+);
+
+-- Bans and unbans are kept here even after the ban itself is gone
+CREATE TABLE IF NOT EXISTS songify_requester_audit (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    uuid VARCHAR(64) NOT NULL,
+    requester VARCHAR(255) NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    actor VARCHAR(64) NOT NULL,
+    reason VARCHAR(255) NULL,
+    created_at BIGINT NOT NULL,
+    INDEX songify_requester_audit_uuid_idx (uuid, id)
+);
+
+ALTER TABLE channel_settings ADD COLUMN requester_cooldown_secs INT NULL;
+
+-- The cooldown looks up a requester's last queued entry
+CREATE INDEX songify_queue_status_actor_idx ON songify_queue_status (Uuid, Actor, Status, CreatedAt);
@@ -0,0 +1,5 @@
+-- Bumped on every queue change and served as the queue's ETag
+CREATE TABLE IF NOT EXISTS songify_queue_versions (
+    Uuid VARCHAR(64) NOT NULL PRIMARY KEY,
+    Version BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
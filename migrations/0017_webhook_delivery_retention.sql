@@ -0,0 +1,2 @@
+-- Old deliveries are pruned by age
+CREATE INDEX songify_webhook_deliveries_created_idx ON songify_webhook_deliveries (created_at);
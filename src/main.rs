@@ -1,19 +1,45 @@
 #[macro_use]
 extern crate serde_json;
 
-use std::{ env, error::Error, fmt };
+use std::{
+    collections::{ HashMap, HashSet },
+    env,
+    error::Error,
+    fmt,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    net::{ IpAddr, Ipv4Addr, SocketAddr },
+    sync::{ atomic::{ AtomicU64, Ordering }, Arc, Mutex, OnceLock },
+    time::Duration,
+};
 
 use rocket::{
+    data::{ IoHandler, IoStream },
     fairing::{ Fairing, Info },
+    futures::{ SinkExt, StreamExt },
     form::FromForm,
+    delete,
     get,
     patch,
     post,
     routes,
-    http::Status,
-    request::Request,
-    response::{ Responder, content::RawText },
+    http::{ Accept, ContentType, Header, Status },
+    request::{ FromRequest, Outcome, Request },
+    response::{
+        Responder,
+        content::{ RawHtml, RawText, RawXml },
+        stream::{ Event, EventStream },
+    },
     serde::{ json::Json, Deserialize, Serialize },
+    tokio::{
+        fs,
+        select,
+        sync::broadcast::{ self, error::RecvError },
+        task,
+        time::{ interval, sleep },
+    },
+    Shutdown,
     State,
 };
 
@@ -21,10 +47,21 @@ use rocket::serde::json::{ json, Value };
 
 use serde_json::{ json as serde_json_macro, Value as SerdeJsonValue };
 
-use reqwest::Client;
+use reqwest::{ redirect::Policy, Client, Url };
 use scraper::{ Html, Selector };
 
-use sqlx::{ mysql::MySqlPoolOptions, FromRow, MySql, Pool, Row };
+use sha2::{ Digest, Sha256 };
+
+use ring::{ hmac, rand::{ SecureRandom, SystemRandom } };
+
+use image::{ imageops::FilterType, ImageFormat, ImageOutputFormat };
+
+use sqlx::{ mysql::MySqlPoolOptions, Executor, FromRow, MySql, Pool, Row, Transaction };
+
+use tokio_tungstenite::{
+    tungstenite::{ handshake::derive_accept_key, protocol::Role, Message },
+    WebSocketStream,
+};
 
 #[derive(Debug)]
 struct ValidationError {
@@ -45,7 +82,7 @@ impl From<ValidationError> for sqlx::Error {
     }
 }
 
-#[derive(Deserialize, Serialize, FromRow)]
+#[derive(Clone, Deserialize, Serialize, FromRow)]
 #[serde(crate = "rocket::serde")]
 struct Song {
     uuid: String,
@@ -56,9 +93,13 @@ struct Song {
     artist: Option<String>,
     title: Option<String>,
     requester: Option<String>,
+    duration_ms: Option<i64>,
+    position_ms: Option<i64>,
+    is_playing: Option<bool>,
+    updated_at: Option<i64>,
 }
 
-#[derive(FromRow, Serialize, Deserialize)]
+#[derive(Clone, FromRow, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 struct QueueSong {
     Queueid: Option<i32>,
@@ -70,6 +111,12 @@ struct QueueSong {
     Requester: String,
     Played: i32,
     Albumcover: Option<String>,
+    Position: Option<i32>,
+    Priority: Option<i32>,
+    LengthMs: Option<i64>,
+    Status: Option<String>,
+    StatusAt: Option<i64>,
+    StatusBy: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -77,6 +124,16 @@ struct QueueSong {
 struct QueuePostPayload {
     queueItem: QueueSong,
     uuid: String,
+    // Higher values are played first, e.g. 1 for subscribers or channel point redemptions
+    priority: Option<i32>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct QueueImportPayload {
+    uuid: String,
+    // Same shape as the export, so a saved queue can be posted back as-is
+    items: Vec<QueueSong>,
 }
 
 #[derive(Deserialize)]
@@ -91,6 +148,9 @@ struct SongPayload {
     artist: Option<String>,
     title: Option<String>,
     requester: Option<String>,
+    duration_ms: Option<i64>,
+    position_ms: Option<i64>,
+    is_playing: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -98,6 +158,18 @@ struct SongPayload {
 struct QueueUpdatePayload {
     queueid: i32,
     uuid: String,
+    // Why the item left the queue, defaults to played
+    status: Option<QueueStatus>,
+    actor: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct QueueMovePayload {
+    uuid: String,
+    queueid: i32,
+    // Zero-based index in the unplayed queue
+    position: usize,
 }
 
 #[derive(Deserialize)]
@@ -105,6 +177,31 @@ struct QueueUpdatePayload {
 struct QueueClearPayload {
     uuid: String,
     key: String,
+    actor: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct QueueRestorePayload {
+    uuid: String,
+    actor: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+enum QueueStatus {
+    Queued,
+    Played,
+    Skipped,
+    Removed,
+    Cleared,
+}
+
+#[derive(FromForm)]
+struct QueueHistoryParams {
+    uuid: String,
+    status: Option<String>,
+    limit: Option<u32>,
 }
 
 #[derive(FromRow)]
@@ -164,8 +261,214 @@ struct QueueParams {
     uuid: Option<String>,
     name: Option<String>,
     full: Option<bool>,
+    format: Option<String>,
+    eta: Option<bool>,
+}
+
+#[derive(FromForm)]
+struct QueueExportParams {
+    uuid: Option<String>,
+    name: Option<String>,
+    filetype: Option<String>,
+}
+
+#[derive(FromForm)]
+struct BatchSongParams {
+    uuid: Vec<String>,
+    name: Vec<String>,
+}
+
+// One row of a batch lookup, `lookup_name` is set for rows found by twitch name
+struct BatchSong {
+    song: Song,
+    lookup_name: Option<String>,
+    stale_after_secs: Option<i32>,
+}
+
+#[derive(Default, FromRow, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ChannelSettings {
+    uuid: String,
+    song_template: Option<String>,
+    overlay_layout: Option<String>,
+    overlay_animation: Option<String>,
+    overlay_font: Option<String>,
+    overlay_text_color: Option<String>,
+    overlay_background_color: Option<String>,
+    overlay_accent_color: Option<String>,
+    idle_text: Option<String>,
+    stale_after_secs: Option<i32>,
+    max_requests_per_user: Option<i32>,
+    max_queue_length: Option<i32>,
+    reject_queued_duplicates: Option<bool>,
+    replay_cooldown_mins: Option<i32>,
+    max_song_length_secs: Option<i32>,
+    auto_advance_queue: Option<bool>,
+    requester_cooldown_secs: Option<i32>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ChannelSettingsPayload {
+    uuid: String,
+    song_template: Option<String>,
+    overlay_layout: Option<String>,
+    overlay_animation: Option<String>,
+    overlay_font: Option<String>,
+    overlay_text_color: Option<String>,
+    overlay_background_color: Option<String>,
+    overlay_accent_color: Option<String>,
+    idle_text: Option<String>,
+    stale_after_secs: Option<i32>,
+    max_requests_per_user: Option<i32>,
+    max_queue_length: Option<i32>,
+    reject_queued_duplicates: Option<bool>,
+    replay_cooldown_mins: Option<i32>,
+    max_song_length_secs: Option<i32>,
+    auto_advance_queue: Option<bool>,
+    requester_cooldown_secs: Option<i32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+enum PlaybackState {
+    Playing,
+    Paused,
+    Stale,
+    #[serde(rename = "none")]
+    Empty,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PlayerType {
+    Spotify,
+    YouTube,
+    YouTubeMusic,
+    AppleMusic,
+    Deezer,
+    Tidal,
+    SoundCloud,
+    Foobar2000,
+    Vlc,
+    Unknown(String),
+}
+
+const OVERLAY_LAYOUTS: [&str; 3] = ["horizontal", "vertical", "text"];
+const OVERLAY_ANIMATIONS: [&str; 3] = ["fade", "slide", "none"];
+// Matches the `type` tag of ChannelUpdate
+const WEBHOOK_EVENTS: [&str; 6] = [
+    "song",
+    "queue_added",
+    "queue_played",
+    "queue_moved",
+    "queue_cleared",
+    "queue_restored",
+];
+
+#[derive(Clone, FromRow, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct Webhook {
+    id: u64,
+    uuid: String,
+    url: String,
+    #[serde(skip_serializing)]
+    secret: String,
+    // Comma separated event names, NULL means every event
+    events: Option<String>,
+    active: bool,
+    created_at: i64,
+}
+
+#[derive(FromRow, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct WebhookDelivery {
+    id: u64,
+    webhook_id: u64,
+    uuid: String,
+    event: String,
+    payload: String,
+    attempt: i32,
+    status_code: Option<i32>,
+    error: Option<String>,
+    success: bool,
+    created_at: i64,
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+enum BlockKind {
+    Track,
+    Artist,
+    Title,
+}
+
+#[derive(FromRow, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct BlocklistEntry {
+    id: u64,
+    uuid: String,
+    kind: String,
+    // Track id, artist name or a title pattern where * matches anything
+    value: String,
+    reason: Option<String>,
+    created_at: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct BlocklistPayload {
+    uuid: String,
+    kind: BlockKind,
+    value: String,
+    reason: Option<String>,
+}
+
+#[derive(FromRow, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RequesterBan {
+    id: u64,
+    uuid: String,
+    requester: String,
+    reason: Option<String>,
+    banned_by: String,
+    // NULL bans permanently
+    expires_at: Option<i64>,
+    created_at: i64,
+}
+
+#[derive(FromRow, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RequesterAudit {
+    id: u64,
+    uuid: String,
+    requester: String,
+    action: String,
+    actor: String,
+    reason: Option<String>,
+    created_at: i64,
 }
 
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RequesterBanPayload {
+    uuid: String,
+    requester: String,
+    reason: Option<String>,
+    actor: Option<String>,
+    duration_secs: Option<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct WebhookPayload {
+    uuid: String,
+    url: String,
+    events: Option<Vec<String>>,
+    // Generated when left out, only ever returned on creation
+    secret: Option<String>,
+}
+
+#[derive(Clone)]
 pub enum QueueParam {
     Id(String),
     Name(String),
@@ -173,6 +476,107 @@ pub enum QueueParam {
 
 struct Cors;
 
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+enum ChannelUpdate {
+    Song {
+        song: Song,
+    },
+    QueueAdded {
+        item: QueueSong,
+    },
+    QueuePlayed {
+        queueid: i32,
+        status: QueueStatus,
+    },
+    QueueMoved {
+        queueid: i32,
+        position: usize,
+    },
+    QueueCleared,
+    QueueRestored {
+        items: Vec<QueueSong>,
+    },
+}
+
+#[derive(Clone)]
+struct ChannelEvent {
+    id: u64,
+    uuid: String,
+    update: ChannelUpdate,
+}
+
+// Why a request was refused by the queue rules, sent to the client as JSON
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct QueueRejection {
+    #[serde(skip)]
+    status: Status,
+    error: &'static str,
+    message: String,
+    limit: Option<usize>,
+}
+
+enum QueueError {
+    Rejected(QueueRejection),
+    Status(Status),
+    Database(sqlx::Error),
+}
+
+// Fan-out for song and queue changes, so overlays can stream instead of polling
+struct ChannelEvents {
+    sender: broadcast::Sender<ChannelEvent>,
+    // Ids restart on every boot, so they're prefixed to keep a reconnect from
+    // matching an id handed out by a previous process
+    boot: i64,
+    next_id: AtomicU64,
+    latest_song: Mutex<HashMap<String, u64>>,
+}
+
+// WebSocket connection pushing the channel snapshot followed by ChannelUpdate deltas
+struct OverlaySocket {
+    uuid: String,
+    snapshot: String,
+    updates: broadcast::Receiver<ChannelEvent>,
+    shutdown: Shutdown,
+}
+
+// Value of the `Last-Event-ID` header an EventSource sends when it reconnects
+struct LastEventId(Option<String>);
+
+// Value of the `If-None-Match` header for conditional GETs
+struct IfNoneMatch(Option<String>);
+struct IfMatch(Option<String>);
+
+// Attaches the queue's ETag to a response
+struct Versioned<R> {
+    inner: R,
+    version: u64,
+}
+
+// Local copies of cover art, keyed by a hash of the upstream URL
+#[derive(Clone)]
+struct CoverCache {
+    dir: PathBuf,
+}
+
+enum CoverResponse {
+    Image {
+        bytes: Vec<u8>,
+        etag: String,
+    },
+    NotModified {
+        etag: String,
+    },
+}
+
+fn now_millis() -> i64 {
+    std::time::SystemTime
+        ::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as i64)
+}
+
 fn get_custom_uuids(uuid: &str) -> &str {
     match uuid {
         "inzaniity" => "43efb299-2504-4365-8ac6-a301f0d7c7aa",
@@ -209,8 +613,10 @@ impl Fairing for Cors {
             )
         );
         response.set_header(
-            rocket::http::Header::new("Access-Control-Allow-Headers", "Content-Type")
+            rocket::http::Header::new("Access-Control-Allow-Headers", "Content-Type, If-Match")
         );
+        // Dashboards read the queue version from the ETag
+        response.set_header(rocket::http::Header::new("Access-Control-Expose-Headers", "ETag"));
     }
 }
 
@@ -237,6 +643,36 @@ impl History {
 
         Ok(history)
     }
+
+    // History only keeps the raw song string, so it's parsed the same way as
+    // now-playing songs and compared by artist and title
+    pub async fn played_since(
+        uuid: &str,
+        song: &QueueSong,
+        minutes: usize,
+        tx: &mut Transaction<'_, MySql>
+    ) -> sqlx::Result<bool> {
+        let since = now_millis() / 1000 - (minutes as i64) * 60;
+
+        let played: Vec<String> = sqlx
+            ::query_scalar(
+                "SELECT song FROM songify_history WHERE uuid = ? AND CAST(tst AS SIGNED) >= ?"
+            )
+            .bind(uuid)
+            .bind(since)
+            .fetch_all(&mut *tx).await?;
+
+        let matches = |value: &Option<String>, expected: &str| {
+            value.as_deref().is_some_and(|value| value.eq_ignore_ascii_case(expected.trim()))
+        };
+
+        Ok(
+            played.iter().any(|played| {
+                let (artist, title) = parse_song_string(played, true);
+                matches(&artist, &song.Artist) && matches(&title, &song.Title)
+            })
+        )
+    }
 }
 
 impl Motd {
@@ -261,385 +697,3722 @@ impl Motd {
     }
 }
 
-impl Song {
-    pub async fn set_song(song: Self, pool: &Pool<MySql>) -> sqlx::Result<()> {
-        let result = sqlx
+impl ChannelSettings {
+    pub async fn get_settings(uuid: String, pool: &Pool<MySql>) -> Result<Self, sqlx::Error> {
+        let settings = sqlx
+            ::query_as::<MySql, Self>("SELECT * FROM channel_settings WHERE uuid = ?")
+            .bind(&uuid)
+            .fetch_optional(pool).await?;
+
+        Ok(settings.unwrap_or(Self { uuid, ..Default::default() }))
+    }
+
+    // Fields left out of the payload keep their stored value, an empty string clears them
+    pub async fn set_settings(
+        settings: ChannelSettingsPayload,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<()> {
+        sqlx
             ::query(
-                "REPLACE INTO song_data 
-            (UUID, song, cover_url, song_id, playertype, artist, title, requester) 
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+                "INSERT INTO channel_settings 
+            (uuid, song_template, overlay_layout, overlay_animation, overlay_font, overlay_text_color, overlay_background_color, overlay_accent_color, idle_text, stale_after_secs, max_requests_per_user, max_queue_length, reject_queued_duplicates, replay_cooldown_mins, max_song_length_secs, auto_advance_queue, requester_cooldown_secs) 
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE 
+            song_template = COALESCE(VALUES(song_template), song_template),
+            overlay_layout = COALESCE(VALUES(overlay_layout), overlay_layout),
+            overlay_animation = COALESCE(VALUES(overlay_animation), overlay_animation),
+            overlay_font = COALESCE(VALUES(overlay_font), overlay_font),
+            overlay_text_color = COALESCE(VALUES(overlay_text_color), overlay_text_color),
+            overlay_background_color = COALESCE(VALUES(overlay_background_color), overlay_background_color),
+            overlay_accent_color = COALESCE(VALUES(overlay_accent_color), overlay_accent_color),
+            idle_text = COALESCE(VALUES(idle_text), idle_text),
+            stale_after_secs = COALESCE(VALUES(stale_after_secs), stale_after_secs),
+            max_requests_per_user = COALESCE(VALUES(max_requests_per_user), max_requests_per_user),
+            max_queue_length = COALESCE(VALUES(max_queue_length), max_queue_length),
+            reject_queued_duplicates = COALESCE(VALUES(reject_queued_duplicates), reject_queued_duplicates),
+            replay_cooldown_mins = COALESCE(VALUES(replay_cooldown_mins), replay_cooldown_mins),
+            max_song_length_secs = COALESCE(VALUES(max_song_length_secs), max_song_length_secs),
+            auto_advance_queue = COALESCE(VALUES(auto_advance_queue), auto_advance_queue),
+            requester_cooldown_secs = COALESCE(VALUES(requester_cooldown_secs), requester_cooldown_secs)"
             )
-            .bind(&song.uuid)
-            .bind(&song.song)
-            .bind(&song.cover_url)
-            .bind(&song.song_id)
-            .bind(&song.playertype)
-            .bind(song.artist.as_deref())
-            .bind(song.title.as_deref())
-            .bind(song.requester.as_deref())
-            .execute(pool).await;
+            .bind(settings.uuid)
+            .bind(settings.song_template)
+            .bind(settings.overlay_layout)
+            .bind(settings.overlay_animation)
+            .bind(settings.overlay_font)
+            .bind(settings.overlay_text_color)
+            .bind(settings.overlay_background_color)
+            .bind(settings.overlay_accent_color)
+            .bind(settings.idle_text)
+            .bind(settings.stale_after_secs)
+            .bind(settings.max_requests_per_user)
+            .bind(settings.max_queue_length)
+            .bind(settings.reject_queued_duplicates)
+            .bind(settings.replay_cooldown_mins)
+            .bind(settings.max_song_length_secs)
+            .bind(settings.auto_advance_queue)
+            .bind(settings.requester_cooldown_secs)
+            .execute(pool).await?;
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                println!("❌ SQL Error: {}", e); // Log any SQL error
-                Err(e)
-            }
-        }
+        Ok(())
     }
 
-    pub async fn get_song(param: QueueParam, pool: &Pool<MySql>) -> Result<Self, sqlx::Error> {
-        // Capture the `id` or `name` before the match expression
-        let (id_or_name, query) = match param {
-            QueueParam::Id(id) =>
-                (
-                    id.clone(), // Clone the id for use later
-                    sqlx
-                        ::query_as::<MySql, Self>("SELECT * FROM song_data WHERE uuid = ?")
-                        .bind(id),
-                ),
-            QueueParam::Name(name) =>
-                (
-                    name.clone(), // Clone the name for use later
-                    sqlx
-                        ::query_as::<MySql, Self>(
-                            "SELECT sd.*
-                     FROM song_data sd
-                     JOIN (
-                         SELECT UUID
-                         FROM songify_usage
-                         WHERE LOWER(twitch_name) = LOWER(?)
-                         ORDER BY tst DESC
-                         LIMIT 1
-                     ) su ON sd.uuid = su.UUID;"
-                        )
-                        .bind(name),
-                ),
-        };
+    const DEFAULT_IDLE_TEXT: &'static str = "No song found";
+    const DEFAULT_STALE_AFTER_SECS: i64 = 600;
 
-        let song_result = query.fetch_one(pool).await;
+    fn idle_text(&self) -> String {
+        Self::value_or(&self.idle_text, Self::DEFAULT_IDLE_TEXT)
+    }
 
-        match song_result {
-            Ok(song) => Ok(song), // If a song is found, return it
-            Err(_) =>
-                Ok(Self {
-                    uuid: id_or_name, // Now you can use the captured id or name
-                    song: "No song found".to_string(),
-                    cover_url: String::new(),
-                    song_id: None,
-                    playertype: None,
-                    artist: None,
-                    title: None,
-                    requester: None,
-                }),
+    // None when the channel hasn't set a TTL (0 counts as unset)
+    fn stale_after_ms(&self) -> Option<i64> {
+        match self.stale_after_secs {
+            Some(secs) if secs > 0 => Some(i64::from(secs) * 1000),
+            _ => None,
+        }
+    }
+
+    // Limits of 0 (or nothing stored) mean no limit
+    fn limit(value: Option<i32>) -> Option<usize> {
+        value.filter(|value| *value > 0).map(|value| value as usize)
+    }
+
+    // Empty strings are how a setting gets cleared, so they count as unset
+    fn value_or(value: &Option<String>, default: &str) -> String {
+        match value.as_deref() {
+            Some(value) if !value.is_empty() => value.to_string(),
+            _ => default.to_string(),
         }
     }
 }
 
-impl QueueSong {
-    pub async fn get_queue(
-        param: QueueParam,
-        pool: &Pool<MySql>
-    ) -> Result<Vec<Self>, sqlx::Error> {
-        let query = match param {
-            QueueParam::Id(id) => {
-                sqlx::query_as::<MySql, Self>(
-                    "SELECT * FROM songify_queue WHERE Uuid = ? AND Played = 0;"
-                ).bind(id)
+impl ChannelSettingsPayload {
+    // Overlay values end up in the page's CSS, so only allow known-safe shapes
+    fn validate(&self) -> Result<(), ValidationError> {
+        fn is_color(value: &str) -> bool {
+            let hex = value.strip_prefix('#').unwrap_or_default();
+            matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+
+        fn check(
+            field: &str,
+            value: &Option<String>,
+            valid: impl Fn(&str) -> bool
+        ) -> Result<(), ValidationError> {
+            match value.as_deref() {
+                Some(value) if !value.is_empty() && !valid(value) =>
+                    Err(ValidationError {
+                        message: format!("Invalid value for {}: {}", field, value),
+                    }),
+                _ => Ok(()),
             }
-            QueueParam::Name(name) => {
-                sqlx::query_as::<MySql, Self>(
-                    "
-                SELECT sq.*
-                FROM songify_queue sq
-                JOIN (
-                    SELECT UUID
-                    FROM songify_usage
-                    WHERE LOWER(twitch_name) = LOWER(?)
-                    ORDER BY tst DESC
-                    LIMIT 1
-                ) su ON sq.Uuid = su.UUID
-                WHERE sq.played = 0
-                ORDER BY sq.Queueid ASC;"
-                ).bind(name)
+        }
+
+        check("overlay_layout", &self.overlay_layout, |v| OVERLAY_LAYOUTS.contains(&v))?;
+        check("overlay_animation", &self.overlay_animation, |v| OVERLAY_ANIMATIONS.contains(&v))?;
+        check("overlay_font", &self.overlay_font, |v| {
+            v.len() <= 64 && v.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-')
+        })?;
+        check("overlay_text_color", &self.overlay_text_color, is_color)?;
+        check("overlay_background_color", &self.overlay_background_color, is_color)?;
+        check("overlay_accent_color", &self.overlay_accent_color, is_color)?;
+
+        fn check_number(field: &str, value: Option<i32>) -> Result<(), ValidationError> {
+            match value {
+                Some(value) if value < 0 =>
+                    Err(ValidationError {
+                        message: format!("{} cannot be negative", field),
+                    }),
+                _ => Ok(()),
             }
-        };
+        }
 
-        let queue = query.fetch_all(pool).await?;
+        check_number("stale_after_secs", self.stale_after_secs)?;
+        check_number("max_requests_per_user", self.max_requests_per_user)?;
+        check_number("max_queue_length", self.max_queue_length)?;
+        check_number("replay_cooldown_mins", self.replay_cooldown_mins)?;
+        check_number("max_song_length_secs", self.max_song_length_secs)?;
+        check_number("requester_cooldown_secs", self.requester_cooldown_secs)?;
 
-        Ok(queue)
+        Ok(())
     }
+}
 
-    pub async fn add_to_queue(id: String, song: Self, pool: &Pool<MySql>) -> sqlx::Result<Self> {
-        use sqlx::Row;
+const SONG_SEPARATORS: [&str; 4] = [" - ", " – ", " — ", " | "];
+const NOISE_WORDS: [&str; 12] = [
+    "official",
+    "video",
+    "audio",
+    "lyric",
+    "lyrics",
+    "visualizer",
+    "visualiser",
+    "hd",
+    "hq",
+    "4k",
+    "mv",
+    "m/v",
+];
 
-        let inserted_song = sqlx
-            ::query(
-                "INSERT INTO songify_queue (Queueid, Uuid, Trackid, Artist, Title, Length, Requester, Played, Albumcover) VALUES (NULL, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING *"
-            )
-            .bind(id)
-            .bind(&song.Trackid)
-            .bind(&song.Artist)
-            .bind(&song.Title)
-            .bind(&song.Length)
-            .bind(&song.Requester)
-            .bind(0)
-            .bind(&song.Albumcover)
-            .fetch_one(pool).await?;
+// Drops bracketed groups like "(Official Video)" or "[Lyrics]", keeping ones
+// such as "(feat. Someone)" or "(Live)" that belong to the title
+fn strip_song_noise(value: &str) -> String {
+    let mut output = String::new();
+    let mut rest = value;
 
-        Ok(Self {
-            Queueid: inserted_song.get(0),
-            Uuid: inserted_song.get(1),
-            Trackid: inserted_song.get(2),
-            Artist: inserted_song.get(3),
-            Title: inserted_song.get(4),
-            Length: inserted_song.get(5),
-            Requester: inserted_song.get(6),
-            Played: inserted_song.get(7),
-            Albumcover: inserted_song.get(8),
-        })
-    }
+    while let Some(start) = rest.find(['(', '[']) {
+        let close = if rest[start..].starts_with('(') { ')' } else { ']' };
+        let Some(end) = rest[start..].find(close).map(|end| start + end) else {
+            break;
+        };
 
-    pub async fn remove_from_queue(
-        uuid: String,
-        queueid: i32,
-        pool: &Pool<MySql>
-    ) -> sqlx::Result<()> {
-        sqlx
-            ::query("UPDATE songify_queue SET Played = 1 WHERE Uuid = ? AND Queueid = ?")
-            .bind(uuid)
-            .bind(queueid)
-            .execute(pool).await?;
+        let group = rest[start + 1..end].to_lowercase();
+        let is_noise = group
+            .split(|c: char| !c.is_alphanumeric() && c != '/')
+            .any(|word| NOISE_WORDS.contains(&word));
 
-        Ok(())
+        output.push_str(&rest[..start]);
+        if !is_noise {
+            output.push_str(&rest[start..=end]);
+        }
+        rest = &rest[end + 1..];
     }
+    output.push_str(rest);
 
-    pub async fn clear_queue(uuid: String, pool: &Pool<MySql>) -> sqlx::Result<()> {
-        sqlx
-            ::query("UPDATE songify_queue SET Played = 1 WHERE Uuid = ?")
-            .bind(uuid)
-            .execute(pool).await?;
+    output.split_whitespace().collect::<Vec<_>>().join(" ")
+}
 
-        Ok(())
+// Splits a raw song string like "Artist - Title" into (artist, title). Without
+// a separator the whole (cleaned) string is returned as the title.
+fn parse_song_string(raw: &str, strip_noise: bool) -> (Option<String>, Option<String>) {
+    let cleaned = if strip_noise { strip_song_noise(raw) } else { raw.trim().to_string() };
+    let non_empty = |value: &str| {
+        let value = value.trim().trim_matches('"').trim();
+        (!value.is_empty()).then(|| value.to_string())
+    };
+
+    for separator in SONG_SEPARATORS {
+        if let Some((artist, title)) = cleaned.split_once(separator) {
+            if let (Some(artist), Some(title)) = (non_empty(artist), non_empty(title)) {
+                return (Some(artist), Some(title));
+            }
+        }
     }
+
+    (None, non_empty(&cleaned))
 }
 
-impl Usage {
-    pub async fn get_access_key(
-        id: String,
-        pool: &Pool<MySql>
-    ) -> Result<Option<String>, sqlx::Error> {
-        let usage = sqlx
-            ::query("SELECT access_key FROM songify_usage WHERE UUID = ?")
-            .bind(id)
-            .fetch_one(pool).await;
+impl PlayerType {
+    // Matching ignores case, spaces, dashes and underscores, so "youtube_music" and
+    // "YouTube Music" end up as the same player
+    fn parse(value: &str) -> Self {
+        let key: String = value
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .collect::<String>()
+            .to_lowercase();
 
-        match usage {
-            Ok(usage) => Ok(Some(usage.get(0))),
-            Err(sqlx::Error::RowNotFound) => Ok(None),
-            Err(e) => { Err(e) }
+        match key.as_str() {
+            "spotify" | "spotifyweb" | "spotifylegacy" | "spotifyapi" => PlayerType::Spotify,
+            "youtube" | "yt" => PlayerType::YouTube,
+            "youtubemusic" | "ytm" | "ytmd" | "ytmusic" => PlayerType::YouTubeMusic,
+            "applemusic" | "itunes" => PlayerType::AppleMusic,
+            "deezer" => PlayerType::Deezer,
+            "tidal" => PlayerType::Tidal,
+            "soundcloud" => PlayerType::SoundCloud,
+            "foobar2000" | "foobar" => PlayerType::Foobar2000,
+            "vlc" => PlayerType::Vlc,
+            _ => PlayerType::Unknown(value.trim().to_string()),
         }
     }
 
-    pub async fn set_access_key(
-        id: String,
-        access_key: String,
-        pool: &Pool<MySql>
-    ) -> sqlx::Result<()> {
-        sqlx
-            ::query("UPDATE songify_usage SET access_key = ? WHERE UUID = ?")
-            .bind(access_key)
-            .bind(id)
-            .execute(pool).await?;
+    fn is_known(&self) -> bool {
+        !matches!(self, PlayerType::Unknown(_))
+    }
 
-        Ok(())
+    fn is_youtube(&self) -> bool {
+        matches!(self, PlayerType::YouTube | PlayerType::YouTubeMusic)
     }
 
-    pub async fn set_telemetry(telemetry: Telemetry, pool: &Pool<MySql>) -> sqlx::Result<()> {
-        if telemetry.uuid.is_empty() {
-            return Err(
-                (ValidationError {
-                    message: "UUID cannot be empty".to_string(),
-                }).into()
-            );
+    // Canonical public url for a track, None for local players or ids we can't link
+    fn track_url(&self, song_id: &str) -> Option<String> {
+        let song_id = song_id.trim();
+
+        if song_id.is_empty() {
+            return None;
         }
-        sqlx
+        if song_id.starts_with("https://") || song_id.starts_with("http://") {
+            return Some(song_id.to_string());
+        }
+
+        match self {
+            PlayerType::Spotify => {
+                let id = song_id.strip_prefix("spotify:track:").unwrap_or(song_id);
+                Some(format!("https://open.spotify.com/track/{}", id))
+            }
+            PlayerType::YouTube => Some(format!("https://www.youtube.com/watch?v={}", song_id)),
+            PlayerType::YouTubeMusic =>
+                Some(format!("https://music.youtube.com/watch?v={}", song_id)),
+            PlayerType::AppleMusic => Some(format!("https://music.apple.com/song/{}", song_id)),
+            PlayerType::Deezer => Some(format!("https://www.deezer.com/track/{}", song_id)),
+            PlayerType::Tidal => Some(format!("https://tidal.com/browse/track/{}", song_id)),
+            PlayerType::SoundCloud | PlayerType::Foobar2000 | PlayerType::Vlc => None,
+            PlayerType::Unknown(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for PlayerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PlayerType::Spotify => "Spotify",
+            PlayerType::YouTube => "YouTube",
+            PlayerType::YouTubeMusic => "YouTube Music",
+            PlayerType::AppleMusic => "Apple Music",
+            PlayerType::Deezer => "Deezer",
+            PlayerType::Tidal => "Tidal",
+            PlayerType::SoundCloud => "SoundCloud",
+            PlayerType::Foobar2000 => "foobar2000",
+            PlayerType::Vlc => "VLC",
+            PlayerType::Unknown(name) => name,
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+// Player types are stored exactly as sent, since variants like "SpotifyWeb" tell us which
+// client mode is in use. PlayerType is only derived when reading.
+fn log_unknown_player_type(uuid: &str, player_type: &PlayerType) {
+    // Each unknown value is logged once per process, with a cap so clients can't grow it forever
+    const MAX_LOGGED: usize = 1000;
+    static LOGGED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+    let PlayerType::Unknown(name) = player_type else {
+        return;
+    };
+
+    let mut logged = LOGGED.get_or_init(Default::default).lock().unwrap();
+    if logged.len() < MAX_LOGGED && logged.insert(name.to_lowercase()) {
+        println!("Unknown player type for {}: {}", uuid, name);
+    }
+}
+
+// Accepts "m:ss", "h:mm:ss" or a raw number of milliseconds
+fn parse_length_ms(value: &str) -> Option<i64> {
+    let value = value.trim();
+
+    if !value.contains(':') {
+        return value.parse::<i64>().ok().filter(|ms| *ms >= 0);
+    }
+
+    let parts = value
+        .split(':')
+        .map(|part| part.trim().parse::<i64>().ok().filter(|part| *part >= 0))
+        .collect::<Option<Vec<_>>>()?;
+
+    // Lengths come from clients and old rows, so overflow is treated like any other bad value
+    let seconds = match parts.as_slice() {
+        [minutes, seconds] if *seconds < 60 => minutes.checked_mul(60)?.checked_add(*seconds)?,
+        [hours, minutes, seconds] if *minutes < 60 && *seconds < 60 =>
+            hours
+                .checked_mul(3600)?
+                .checked_add(minutes * 60)?
+                .checked_add(*seconds)?,
+        _ => {
+            return None;
+        }
+    };
+
+    seconds.checked_mul(1000)
+}
+
+fn format_length(secs: usize) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+impl Song {
+    // Nothing real is longer than a day, larger values would only overflow the math below
+    const MAX_TRACK_MS: i64 = 24 * 60 * 60 * 1000;
+
+    fn empty(uuid: String, text: String) -> Self {
+        Self {
+            uuid,
+            song: text,
+            cover_url: String::new(),
+            song_id: None,
+            playertype: None,
+            artist: None,
+            title: None,
+            requester: None,
+            duration_ms: None,
+            position_ms: None,
+            is_playing: None,
+            updated_at: None,
+        }
+    }
+
+    fn player_type(&self) -> Option<PlayerType> {
+        self.playertype.as_deref().map(PlayerType::parse)
+    }
+
+    // A song counts as stale once it hasn't been updated for the TTL past the
+    // point where it should have finished playing. Without a known duration
+    // only a TTL the channel set explicitly applies.
+    pub fn state(&self, stale_after_ms: Option<i64>) -> PlaybackState {
+        let playing = self.is_playing.unwrap_or(true);
+
+        // Rows written before updates were tracked have no age to judge by
+        if let Some(updated_at) = self.updated_at {
+            let ttl = match (self.duration_ms, stale_after_ms) {
+                (_, Some(ttl)) => Some(ttl),
+                (Some(_), None) => Some(ChannelSettings::DEFAULT_STALE_AFTER_SECS * 1000),
+                (None, None) => None,
+            };
+
+            if let Some(ttl) = ttl {
+                let remaining = match (playing, self.duration_ms, self.position_ms) {
+                    (true, Some(duration), Some(position)) => duration.saturating_sub(position).max(0),
+                    (true, Some(duration), None) => duration,
+                    _ => 0,
+                };
+
+                if now_millis().saturating_sub(updated_at) > remaining.saturating_add(ttl) {
+                    return PlaybackState::Stale;
+                }
+            }
+        }
+
+        if playing {
+            PlaybackState::Playing
+        } else {
+            PlaybackState::Paused
+        }
+    }
+
+    // Time left on the current song, 0 when that can't be known
+    pub fn remaining_ms(&self, state: PlaybackState) -> i64 {
+        if matches!(state, PlaybackState::Stale | PlaybackState::Empty) {
+            return 0;
+        }
+
+        match (self.duration_ms, self.current_position()) {
+            (Some(duration), Some(position)) => duration.saturating_sub(position).max(0),
+            (Some(duration), None) => duration,
+            _ => 0,
+        }
+    }
+
+    // Where playback should be by now, assuming it kept running since the last update
+    pub fn current_position(&self) -> Option<i64> {
+        let position = self.position_ms?;
+
+        if !self.is_playing.unwrap_or(false) {
+            return Some(position);
+        }
+
+        let elapsed = self.updated_at.map_or(0, |updated_at|
+            now_millis().saturating_sub(updated_at).max(0)
+        );
+        let position = position.saturating_add(elapsed);
+
+        Some(match self.duration_ms {
+            Some(duration) => position.min(duration),
+            None => position,
+        })
+    }
+
+    fn template_value(&self, key: &str) -> Option<Option<String>> {
+        let value = match key {
+            "song" => Some(self.song.clone()),
+            "artist" => self.artist.clone(),
+            "title" => self.title.clone(),
+            "requester" => self.requester.clone(),
+            "cover" => Some(self.cover_url.clone()),
+            "id" => self.song_id.clone(),
+            _ => {
+                return None;
+            }
+        };
+
+        Some(value.filter(|value| !value.is_empty()))
+    }
+
+    // Renders a template like "{artist} - {title}[ (requested by {requester})]".
+    // `{field|text}` uses `text` when the field is empty, and a `[...]` section is
+    // dropped entirely if one of its fields is empty and has no fallback.
+    pub fn render(&self, template: &str) -> String {
+        let mut output = String::new();
+        let mut section = String::new();
+        let mut in_section = false;
+        let mut section_complete = true;
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            let target = if in_section { &mut section } else { &mut output };
+
+            match c {
+                '{' => {
+                    let placeholder: String = chars
+                        .by_ref()
+                        .take_while(|c| *c != '}')
+                        .collect();
+                    let (key, fallback) = match placeholder.split_once('|') {
+                        Some((key, fallback)) => (key.trim(), Some(fallback)),
+                        None => (placeholder.trim(), None),
+                    };
+
+                    match (self.template_value(key), fallback) {
+                        // Keep unknown placeholders so typos are visible in the output
+                        (None, _) => {
+                            target.push('{');
+                            target.push_str(&placeholder);
+                            target.push('}');
+                        }
+                        (Some(Some(value)), _) => target.push_str(&value),
+                        (Some(None), Some(fallback)) => target.push_str(fallback),
+                        (Some(None), None) => {
+                            section_complete = false;
+                            // Without a title the raw song string is the best we have
+                            if key == "title" && !in_section {
+                                target.push_str(&self.song);
+                            }
+                        }
+                    }
+                }
+                '[' if !in_section => {
+                    in_section = true;
+                    section_complete = true;
+                    section.clear();
+                }
+                ']' if in_section => {
+                    in_section = false;
+                    if section_complete {
+                        output.push_str(&section);
+                    }
+                }
+                c => target.push(c),
+            }
+        }
+
+        // An unterminated section is treated as literal text
+        if in_section {
+            output.push('[');
+            output.push_str(&section);
+        }
+
+        output.trim().to_string()
+    }
+
+    // Fills artist and title from the raw song string for clients that only send the latter
+    pub fn normalize(&mut self) {
+        let missing = |value: &Option<String>| value.as_deref().is_none_or(str::is_empty);
+
+        if !missing(&self.artist) && !missing(&self.title) {
+            return;
+        }
+
+        let youtube = self.player_type().is_some_and(|player_type| player_type.is_youtube());
+        let (artist, title) = parse_song_string(&self.song, youtube);
+
+        if missing(&self.artist) && artist.is_some() {
+            self.artist = artist;
+        }
+        if missing(&self.title) && title.is_some() {
+            self.title = title;
+        }
+    }
+
+    // Also returns the queue item that was marked played when the channel has
+    // auto_advance_queue enabled and the new song is waiting in the queue
+    pub async fn set_song(mut song: Self, pool: &Pool<MySql>) -> sqlx::Result<(Self, Option<i32>)> {
+        song.normalize();
+
+        let settings = ChannelSettings::get_settings(song.uuid.clone(), pool).await?;
+
+        // Only channels that advance their queue need the row lock
+        if !settings.auto_advance_queue.unwrap_or(false) {
+            Self::store(&song, pool).await?;
+            return Ok((song, None));
+        }
+
+        let mut tx = pool.begin().await?;
+
+        // Position and pause updates re-post the same track, only a new track advances the queue
+        let previous_id: Option<Option<String>> = sqlx
+            ::query_scalar("SELECT song_id FROM song_data WHERE UUID = ? FOR UPDATE")
+            .bind(&song.uuid)
+            .fetch_optional(&mut tx).await?;
+        let track_changed = previous_id.flatten() != song.song_id;
+
+        Self::store(&song, &mut tx).await?;
+
+        let advanced = if track_changed {
+            Self::advance_queue(&song, &mut tx).await?
+        } else {
+            None
+        };
+
+        tx.commit().await?;
+
+        Ok((song, advanced))
+    }
+
+    async fn store<'c, E>(song: &Self, executor: E) -> sqlx::Result<()>
+        where E: Executor<'c, Database = MySql>
+    {
+        let result = sqlx
             ::query(
-                "REPLACE INTO songify_usage (UUID, tst, twitch_id, twitch_name, vs, playertype, access_key) VALUES (?, ?, ?, ?, ?, ?, ?)"
+                "REPLACE INTO song_data 
+            (UUID, song, cover_url, song_id, playertype, artist, title, requester, duration_ms, position_ms, is_playing, updated_at) 
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
             )
-            .bind(telemetry.uuid)
-            .bind(telemetry.tst.to_string())
-            .bind(telemetry.twitch_id)
-            .bind(telemetry.twitch_name)
-            .bind(telemetry.vs)
-            .bind(telemetry.playertype)
-            .bind(telemetry.key)
-            .execute(pool).await?;
+            .bind(&song.uuid)
+            .bind(&song.song)
+            .bind(&song.cover_url)
+            .bind(&song.song_id)
+            .bind(&song.playertype)
+            .bind(song.artist.as_deref())
+            .bind(song.title.as_deref())
+            .bind(song.requester.as_deref())
+            .bind(song.duration_ms)
+            .bind(song.position_ms)
+            .bind(song.is_playing)
+            .bind(song.updated_at)
+            .execute(executor).await;
+
+        if let Err(e) = result {
+            println!("❌ SQL Error: {}", e); // Log any SQL error
+            return Err(e);
+        }
 
         Ok(())
     }
 
-    pub async fn get_twitch_name(
-        id: String,
+    // Marks the first unplayed item with the same track id as played, so the head
+    // wins when a song was requested more than once
+    async fn advance_queue(
+        song: &Self,
+        tx: &mut Transaction<'_, MySql>
+    ) -> sqlx::Result<Option<i32>> {
+        let Some(song_id) = song.song_id.as_deref().map(str::trim).filter(|id| !id.is_empty()) else {
+            return Ok(None);
+        };
+
+        QueueSong::lock_version(&song.uuid, tx).await?;
+
+        let queueid: Option<i32> = sqlx
+            ::query_scalar(
+                "SELECT Queueid FROM songify_queue WHERE Uuid = ? AND Played = 0 AND Trackid = ? ORDER BY Priority DESC, Position ASC, Queueid ASC LIMIT 1 FOR UPDATE"
+            )
+            .bind(&song.uuid)
+            .bind(song_id)
+            .fetch_optional(&mut *tx).await?;
+
+        let Some(queueid) = queueid else {
+            return Ok(None);
+        };
+
+        sqlx
+            ::query(
+                "UPDATE songify_queue SET Played = 1, Status = ?, StatusAt = ?, StatusBy = ? WHERE Queueid = ?"
+            )
+            .bind(QueueStatus::Played.as_str())
+            .bind(now_millis())
+            .bind("auto")
+            .bind(queueid)
+            .execute(&mut *tx).await?;
+
+        QueueSong::log_status(&song.uuid, queueid, QueueStatus::Played, "auto", tx).await?;
+        QueueSong::bump_version(&song.uuid, tx).await?;
+
+        Ok(Some(queueid))
+    }
+
+    pub async fn get_song(
+        param: QueueParam,
         pool: &Pool<MySql>
-    ) -> Result<Option<String>, sqlx::Error> {
-        let usage = sqlx
-            ::query("SELECT twitch_name FROM songify_usage WHERE UUID = ?")
-            .bind(id)
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let query = match param {
+            QueueParam::Id(id) =>
+                sqlx::query_as::<MySql, Self>("SELECT * FROM song_data WHERE uuid = ?").bind(id),
+            QueueParam::Name(name) =>
+                sqlx
+                    ::query_as::<MySql, Self>(
+                        "SELECT sd.*
+                     FROM song_data sd
+                     JOIN (
+                         SELECT UUID
+                         FROM songify_usage
+                         WHERE LOWER(twitch_name) = LOWER(?)
+                         ORDER BY tst DESC
+                         LIMIT 1
+                     ) su ON sd.uuid = su.UUID;"
+                    )
+                    .bind(name),
+        };
+
+        let song = query.fetch_optional(pool).await?;
+
+        Ok(song)
+    }
+}
+
+impl BlockKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BlockKind::Track => "track",
+            BlockKind::Artist => "artist",
+            BlockKind::Title => "title",
+        }
+    }
+}
+
+impl BlocklistEntry {
+    pub async fn get_entries(uuid: &str, pool: &Pool<MySql>) -> sqlx::Result<Vec<Self>> {
+        sqlx
+            ::query_as::<MySql, Self>("SELECT * FROM songify_blocklist WHERE uuid = ? ORDER BY id")
+            .bind(uuid)
+            .fetch_all(pool).await
+    }
+
+    // Returns None when the same entry already exists
+    pub async fn add_entry(
+        payload: BlocklistPayload,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<Option<Self>> {
+        let result = sqlx
+            ::query_as::<MySql, Self>(
+                "INSERT INTO songify_blocklist (uuid, kind, value, reason, created_at) VALUES (?, ?, ?, ?, ?) RETURNING *"
+            )
+            .bind(&payload.uuid)
+            .bind(payload.kind.as_str())
+            .bind(payload.value.trim())
+            .bind(payload.reason.filter(|reason| !reason.trim().is_empty()))
+            .bind(now_millis())
             .fetch_one(pool).await;
 
-        match usage {
-            Ok(usage) => Ok(Some(usage.get(0))),
-            Err(sqlx::Error::RowNotFound) => Ok(None),
-            Err(e) => { Err(e) }
+        match result {
+            Ok(entry) => Ok(Some(entry)),
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("23000") => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn delete_entry(uuid: &str, id: u64, pool: &Pool<MySql>) -> sqlx::Result<bool> {
+        let result = sqlx
+            ::query("DELETE FROM songify_blocklist WHERE uuid = ? AND id = ?")
+            .bind(uuid)
+            .bind(id)
+            .execute(pool).await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Artists also match one of several credited artists, e.g. "A, B & C"
+    fn matches(&self, song: &QueueSong) -> bool {
+        match self.kind.as_str() {
+            "track" => !song.Trackid.is_empty() && song.Trackid == self.value,
+            "artist" =>
+                song.Artist
+                    .split([',', '&'])
+                    .chain(std::iter::once(song.Artist.as_str()))
+                    .any(|artist| artist.trim().eq_ignore_ascii_case(&self.value)),
+            "title" => wildcard_match(&self.value.to_lowercase(), &song.Title.to_lowercase()),
+            _ => false,
+        }
+    }
+
+    fn rejection_message(&self, song: &QueueSong) -> String {
+        let message = match self.kind.as_str() {
+            "artist" => format!("Songs by {} are blocked on this channel", self.value),
+            _ => format!("{} - {} is blocked on this channel", song.Artist, song.Title),
+        };
+
+        match &self.reason {
+            Some(reason) => format!("{}: {}", message, reason),
+            None => message,
         }
     }
 }
 
-async fn verify_access_key(
-    uuid: &str,
+// `*` matches any run of characters, everything else has to match exactly
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if
+        text.len() < first.len() + last.len() ||
+        !text.starts_with(first) ||
+        !text.ends_with(last)
+    {
+        return false;
+    }
+
+    let mut rest = &text[first.len()..text.len() - last.len()];
+
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => {
+                rest = &rest[index + part.len()..];
+            }
+            None => {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+impl RequesterBan {
+    // Longer temporary bans are rejected, leave out duration_secs for a permanent one
+    const MAX_DURATION_SECS: i64 = 10 * 365 * 24 * 60 * 60;
+
+    pub async fn get_bans(uuid: &str, pool: &Pool<MySql>) -> sqlx::Result<Vec<Self>> {
+        sqlx
+            ::query_as::<MySql, Self>(
+                "SELECT * FROM songify_requester_bans WHERE uuid = ? AND (expires_at IS NULL OR expires_at > ?) ORDER BY created_at DESC"
+            )
+            .bind(uuid)
+            .bind(now_millis())
+            .fetch_all(pool).await
+    }
+
+    // Banning someone who is already banned replaces the old ban
+    pub async fn ban(payload: RequesterBanPayload, pool: &Pool<MySql>) -> sqlx::Result<Self> {
+        let mut tx = pool.begin().await?;
+        let now = now_millis();
+        let requester = payload.requester.trim().to_string();
+        let actor = payload.actor.unwrap_or_else(|| "client".to_string());
+        let reason = payload.reason.filter(|reason| !reason.trim().is_empty());
+
+        sqlx
+            ::query(
+                "INSERT INTO songify_requester_bans (uuid, requester, reason, banned_by, expires_at, created_at) VALUES (?, ?, ?, ?, ?, ?) 
+            ON DUPLICATE KEY UPDATE reason = VALUES(reason), banned_by = VALUES(banned_by), expires_at = VALUES(expires_at), created_at = VALUES(created_at)"
+            )
+            .bind(&payload.uuid)
+            .bind(&requester)
+            .bind(&reason)
+            .bind(&actor)
+            .bind(payload.duration_secs.map(|secs| now + secs.min(Self::MAX_DURATION_SECS) * 1000))
+            .bind(now)
+            .execute(&mut tx).await?;
+
+        Self::audit(&payload.uuid, &requester, "ban", &actor, reason.as_deref(), &mut tx).await?;
+
+        let ban = sqlx
+            ::query_as::<MySql, Self>(
+                "SELECT * FROM songify_requester_bans WHERE uuid = ? AND requester = ?"
+            )
+            .bind(&payload.uuid)
+            .bind(&requester)
+            .fetch_one(&mut tx).await?;
+
+        tx.commit().await?;
+
+        Ok(ban)
+    }
+
+    pub async fn unban(
+        uuid: &str,
+        id: u64,
+        actor: String,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<bool> {
+        let mut tx = pool.begin().await?;
+
+        let ban = sqlx
+            ::query_as::<MySql, Self>(
+                "SELECT * FROM songify_requester_bans WHERE uuid = ? AND id = ? FOR UPDATE"
+            )
+            .bind(uuid)
+            .bind(id)
+            .fetch_optional(&mut tx).await?;
+
+        let Some(ban) = ban else {
+            return Ok(false);
+        };
+
+        sqlx
+            ::query("DELETE FROM songify_requester_bans WHERE id = ?")
+            .bind(id)
+            .execute(&mut tx).await?;
+
+        Self::audit(uuid, &ban.requester, "unban", &actor, None, &mut tx).await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    pub async fn get_audit(
+        uuid: &str,
+        limit: u32,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<Vec<RequesterAudit>> {
+        sqlx
+            ::query_as::<MySql, RequesterAudit>(
+                "SELECT * FROM songify_requester_audit WHERE uuid = ? ORDER BY id DESC LIMIT ?"
+            )
+            .bind(uuid)
+            .bind(limit)
+            .fetch_all(pool).await
+    }
+
+    async fn audit(
+        uuid: &str,
+        requester: &str,
+        action: &str,
+        actor: &str,
+        reason: Option<&str>,
+        tx: &mut Transaction<'_, MySql>
+    ) -> sqlx::Result<()> {
+        sqlx
+            ::query(
+                "INSERT INTO songify_requester_audit (uuid, requester, action, actor, reason, created_at) VALUES (?, ?, ?, ?, ?, ?)"
+            )
+            .bind(uuid)
+            .bind(requester)
+            .bind(action)
+            .bind(actor)
+            .bind(reason)
+            .bind(now_millis())
+            .execute(&mut *tx).await?;
+
+        Ok(())
+    }
+}
+
+impl WebhookPayload {
+    fn validate(&self) -> Result<(), ValidationError> {
+        let invalid = |message: String| Err(ValidationError { message });
+
+        match Url::parse(&self.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => (),
+            _ => {
+                return invalid(format!("Invalid webhook url: {}", self.url));
+            }
+        }
+
+        if let Some(events) = &self.events {
+            if events.is_empty() {
+                return invalid("events cannot be empty".to_string());
+            }
+            if let Some(event) = events.iter().find(|e| !WEBHOOK_EVENTS.contains(&e.as_str())) {
+                return invalid(format!("Unknown webhook event: {}", event));
+            }
+        }
+
+        if self.secret.as_ref().is_some_and(|secret| secret.len() < 16) {
+            return invalid("secret must be at least 16 characters".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// Channels with at least one active webhook, so events for everyone else never
+// touch the database
+struct WebhookChannels {
+    uuids: Mutex<HashSet<String>>,
+}
+
+impl WebhookChannels {
+    async fn load(pool: &Pool<MySql>) -> sqlx::Result<Self> {
+        let uuids: Vec<String> = sqlx
+            ::query_scalar("SELECT DISTINCT uuid FROM songify_webhooks WHERE active = 1")
+            .fetch_all(pool).await?;
+
+        Ok(Self { uuids: Mutex::new(uuids.into_iter().collect()) })
+    }
+
+    fn contains(&self, uuid: &str) -> bool {
+        self.uuids.lock().unwrap().contains(uuid)
+    }
+
+    // Called after a channel's webhooks were added or removed
+    async fn refresh(&self, uuid: &str, pool: &Pool<MySql>) -> sqlx::Result<()> {
+        let count: i64 = sqlx
+            ::query_scalar("SELECT COUNT(*) FROM songify_webhooks WHERE uuid = ? AND active = 1")
+            .bind(uuid)
+            .fetch_one(pool).await?;
+
+        let mut uuids = self.uuids.lock().unwrap();
+        if count > 0 {
+            uuids.insert(uuid.to_string());
+        } else {
+            uuids.remove(uuid);
+        }
+
+        Ok(())
+    }
+}
+
+impl Webhook {
+    const MAX_PER_CHANNEL: i64 = 10;
+    const MAX_ATTEMPTS: i32 = 5;
+    // Doubled after every failed attempt: 2s, 4s, 8s, 16s
+    const RETRY_DELAY: Duration = Duration::from_secs(2);
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    const DELIVERY_RETENTION_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+    pub async fn get_webhooks(uuid: &str, pool: &Pool<MySql>) -> sqlx::Result<Vec<Self>> {
+        sqlx
+            ::query_as::<MySql, Self>("SELECT * FROM songify_webhooks WHERE uuid = ? ORDER BY id")
+            .bind(uuid)
+            .fetch_all(pool).await
+    }
+
+    // Returns None when the channel already has the maximum number of webhooks
+    pub async fn add_webhook(
+        payload: WebhookPayload,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<Option<Self>> {
+        let mut tx = pool.begin().await?;
+
+        let count: i64 = sqlx
+            ::query_scalar("SELECT COUNT(*) FROM songify_webhooks WHERE uuid = ? FOR UPDATE")
+            .bind(&payload.uuid)
+            .fetch_one(&mut tx).await?;
+
+        if count >= Self::MAX_PER_CHANNEL {
+            return Ok(None);
+        }
+
+        let secret = match payload.secret {
+            Some(secret) => secret,
+            None => Self::generate_secret()?,
+        };
+
+        let webhook = sqlx
+            ::query_as::<MySql, Self>(
+                "INSERT INTO songify_webhooks (uuid, url, secret, events, active, created_at) VALUES (?, ?, ?, ?, 1, ?) RETURNING *"
+            )
+            .bind(&payload.uuid)
+            .bind(&payload.url)
+            .bind(secret)
+            .bind(payload.events.map(|events| events.join(",")))
+            .bind(now_millis())
+            .fetch_one(&mut tx).await?;
+
+        tx.commit().await?;
+
+        Ok(Some(webhook))
+    }
+
+    pub async fn delete_webhook(uuid: &str, id: u64, pool: &Pool<MySql>) -> sqlx::Result<bool> {
+        let result = sqlx
+            ::query("DELETE FROM songify_webhooks WHERE uuid = ? AND id = ?")
+            .bind(uuid)
+            .bind(id)
+            .execute(pool).await?;
+
+        sqlx
+            ::query("DELETE FROM songify_webhook_deliveries WHERE uuid = ? AND webhook_id = ?")
+            .bind(uuid)
+            .bind(id)
+            .execute(pool).await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // The delivery log is for debugging recent failures, older rows are dropped
+    pub async fn prune_deliveries(pool: &Pool<MySql>) -> sqlx::Result<u64> {
+        let result = sqlx
+            ::query("DELETE FROM songify_webhook_deliveries WHERE created_at < ?")
+            .bind(now_millis() - Self::DELIVERY_RETENTION_MS)
+            .execute(pool).await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_deliveries(
+        uuid: &str,
+        limit: u32,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<Vec<WebhookDelivery>> {
+        sqlx
+            ::query_as::<MySql, WebhookDelivery>(
+                "SELECT * FROM songify_webhook_deliveries WHERE uuid = ? ORDER BY id DESC LIMIT ?"
+            )
+            .bind(uuid)
+            .bind(limit)
+            .fetch_all(pool).await
+    }
+
+    // Only public hosts, otherwise the delivery log would report on the internal network
+    async fn client(url: &str) -> Result<Client, String> {
+        let url = Url::parse(url).map_err(|err| err.to_string())?;
+        public_client(&url, Self::TIMEOUT).await
+    }
+
+    fn generate_secret() -> sqlx::Result<String> {
+        let mut bytes = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| sqlx::Error::Protocol("Could not generate webhook secret".to_string()))?;
+
+        Ok(to_hex(&bytes))
+    }
+
+    fn wants(&self, event: &str) -> bool {
+        match &self.events {
+            Some(events) => events.split(',').any(|e| e == event),
+            None => true,
+        }
+    }
+
+    // Receivers recompute this over "<timestamp>.<body>" with their secret
+    fn sign(&self, timestamp: i64, body: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, self.secret.as_bytes());
+        let tag = hmac::sign(&key, format!("{}.{}", timestamp, body).as_bytes());
+        to_hex(tag.as_ref())
+    }
+
+    // Runs for the lifetime of the server, every ChannelUpdate is a webhook event. Song
+    // updates are also posted for position and pause changes, those only fire once the
+    // track actually changes.
+    pub async fn dispatch(
+        mut events: broadcast::Receiver<ChannelEvent>,
+        channels: Arc<WebhookChannels>,
+        pool: Pool<MySql>
+    ) {
+        let mut current_tracks: HashMap<String, String> = HashMap::new();
+
+        loop {
+            match events.recv().await {
+                Ok(event) if channels.contains(&event.uuid) => {
+                    if let ChannelUpdate::Song { song } = &event.update {
+                        let track = song.song_id.clone().unwrap_or_else(|| song.song.clone());
+                        if current_tracks.get(&event.uuid) == Some(&track) {
+                            continue;
+                        }
+                        current_tracks.insert(event.uuid.clone(), track);
+                    }
+
+                    task::spawn(Self::fire(event, pool.clone()));
+                }
+                Ok(_) => (),
+                Err(RecvError::Lagged(skipped)) => {
+                    eprintln!("Webhook dispatcher fell behind, {} events were not delivered", skipped);
+                }
+                Err(RecvError::Closed) => {
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn fire(event: ChannelEvent, pool: Pool<MySql>) {
+        let webhooks = match
+            sqlx
+                ::query_as::<MySql, Self>(
+                    "SELECT * FROM songify_webhooks WHERE uuid = ? AND active = 1"
+                )
+                .bind(&event.uuid)
+                .fetch_all(&pool).await
+        {
+            Ok(webhooks) => webhooks,
+            Err(e) => {
+                eprintln!("Could not load webhooks for {}: {}", event.uuid, e);
+                return;
+            }
+        };
+
+        if webhooks.is_empty() {
+            return;
+        }
+
+        let data = json!(event.update);
+        let name = data["type"].as_str().unwrap_or_default().to_string();
+        let body =
+            json!({
+            "id": event.id,
+            "event": name,
+            "uuid": event.uuid,
+            "created_at": now_millis(),
+            "data": data,
+        }).to_string();
+
+        for webhook in webhooks.into_iter().filter(|webhook| webhook.wants(&name)) {
+            task::spawn(
+                webhook.deliver(event.id, name.clone(), body.clone(), pool.clone())
+            );
+        }
+    }
+
+    async fn deliver(
+        self,
+        delivery: u64,
+        event: String,
+        body: String,
+        pool: Pool<MySql>
+    ) {
+        let mut delay = Self::RETRY_DELAY;
+
+        for attempt in 1..=Self::MAX_ATTEMPTS {
+            let timestamp = now_millis() / 1000;
+
+            let client = match Self::client(&self.url).await {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Not delivering webhook {}: {}", self.id, e);
+                    return;
+                }
+            };
+
+            let result = client
+                .post(&self.url)
+                .header("Content-Type", "application/json")
+                .header("X-Songify-Event", &event)
+                .header("X-Songify-Delivery", delivery.to_string())
+                .header("X-Songify-Timestamp", timestamp.to_string())
+                .header("X-Songify-Signature", format!("sha256={}", self.sign(timestamp, &body)))
+                .body(body.clone())
+                .send().await;
+
+            let (status, error) = match result {
+                Ok(response) if response.status().is_success() => (Some(response.status()), None),
+                Ok(response) =>
+                    (Some(response.status()), Some(format!("HTTP {}", response.status()))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+
+            let log = sqlx
+                ::query(
+                    "INSERT INTO songify_webhook_deliveries (webhook_id, uuid, event, payload, attempt, status_code, error, success, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+                )
+                .bind(self.id)
+                .bind(&self.uuid)
+                .bind(&event)
+                .bind(&body)
+                .bind(attempt)
+                .bind(status.map(|status| i32::from(status.as_u16())))
+                .bind(&error)
+                .bind(error.is_none())
+                .bind(now_millis())
+                .execute(&pool).await;
+
+            if let Err(e) = log {
+                eprintln!("Could not log webhook delivery {}: {}", self.id, e);
+            }
+
+            // Client errors other than timeouts and rate limits won't go away by retrying
+            let retry = match status {
+                None => true,
+                Some(status) =>
+                    status.is_server_error() ||
+                    status == reqwest::StatusCode::REQUEST_TIMEOUT ||
+                    status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+            };
+
+            if error.is_none() || !retry || attempt == Self::MAX_ATTEMPTS {
+                return;
+            }
+
+            sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl ChannelEvents {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(1024);
+
+        Self {
+            sender,
+            boot: now_millis(),
+            next_id: AtomicU64::new(1),
+            latest_song: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn publish(&self, uuid: String, update: ChannelUpdate) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let ChannelUpdate::Song { .. } = update {
+            self.latest_song.lock().unwrap().insert(uuid.clone(), id);
+        }
+
+        // Sending only fails when nobody is listening, which is fine
+        let _ = self.sender.send(ChannelEvent { id, uuid, update });
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<ChannelEvent> {
+        self.sender.subscribe()
+    }
+
+    fn event_id(&self, id: u64) -> String {
+        format!("{}-{}", self.boot, id)
+    }
+
+    fn latest_song_id(&self, uuid: &str) -> Option<String> {
+        let id = self.latest_song.lock().unwrap().get(uuid).copied()?;
+        Some(self.event_id(id))
+    }
+}
+
+impl<'r> Responder<'r, 'static> for OverlaySocket {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let upgrade = req.headers().get_one("Upgrade").unwrap_or_default();
+        if !upgrade.eq_ignore_ascii_case("websocket") {
+            return Err(Status::UpgradeRequired);
+        }
+
+        let key = req.headers().get_one("Sec-WebSocket-Key").ok_or(Status::BadRequest)?;
+
+        rocket::Response
+            ::build()
+            .raw_header("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes()))
+            .upgrade("websocket", self)
+            .ok()
+    }
+}
+
+#[rocket::async_trait]
+impl IoHandler for OverlaySocket {
+    async fn io(self: Pin<Box<Self>>, io: IoStream) -> io::Result<()> {
+        let OverlaySocket { uuid, snapshot, mut updates, mut shutdown } = *Pin::into_inner(self);
+        let mut socket = WebSocketStream::from_raw_socket(io, Role::Server, None).await;
+        let mut ping = interval(Duration::from_secs(30));
+
+        socket.send(Message::Text(snapshot)).await.map_err(io::Error::other)?;
+
+        loop {
+            select! {
+                msg = updates.recv() => match msg {
+                    Ok(event) if event.uuid == uuid => {
+                        let text = serde_json::to_string(&event.update)?;
+                        socket.send(Message::Text(text)).await.map_err(io::Error::other)?;
+                    }
+                    Ok(_) => (),
+                    // Missed deltas would leave the overlay out of sync, so make it
+                    // reconnect and pick up a fresh snapshot instead
+                    Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => break,
+                },
+                msg = socket.next() => match msg {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => (),
+                },
+                _ = ping.tick() => {
+                    socket.send(Message::Ping(Vec::new())).await.map_err(io::Error::other)?;
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+
+        let _ = socket.close(None).await;
+
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(LastEventId(req.headers().get_one("Last-Event-ID").map(String::from)))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(req.headers().get_one("If-None-Match").map(String::from)))
+    }
+}
+
+// Client-supplied urls (cover art, webhooks) must not reach the server's own network
+fn is_public_ip(ip: IpAddr) -> bool {
+    fn is_public_v4(ip: Ipv4Addr) -> bool {
+        let [a, b, ..] = ip.octets();
+        !(
+            ip.is_unspecified() ||
+            ip.is_loopback() ||
+            ip.is_private() ||
+            ip.is_link_local() ||
+            ip.is_broadcast() ||
+            ip.is_documentation() ||
+            ip.is_multicast() ||
+            a == 0 ||
+            a >= 240 ||
+            // Carrier-grade NAT
+            (a == 100 && (64..128).contains(&b))
+        )
+    }
+
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_public_v4(mapped);
+            }
+            let first = ip.segments()[0];
+            !(
+                ip.is_unspecified() ||
+                ip.is_loopback() ||
+                ip.is_multicast() ||
+                // Unique local fc00::/7 and link-local fe80::/10
+                (first & 0xfe00) == 0xfc00 ||
+                (first & 0xffc0) == 0xfe80
+            )
+        }
+    }
+}
+
+// Resolves the url's host once and pins the returned client to those addresses, so a
+// second lookup can't be pointed at a private address. Redirects aren't followed for
+// the same reason.
+async fn public_client(url: &Url, timeout: Duration) -> Result<Client, String> {
+    let host = url.host_str().ok_or_else(|| format!("{} has no host", url))?;
+    let port = url.port_or_known_default().ok_or_else(|| format!("{} has no port", url))?;
+
+    let builder = Client::builder().timeout(timeout).redirect(Policy::none());
+
+    // IPv6 literals keep their brackets in host_str
+    let builder = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) if is_public_ip(ip) => builder,
+        Ok(_) => {
+            return Err(format!("{} is not a public address", host));
+        }
+        Err(_) => {
+            let addrs: Vec<SocketAddr> = rocket::tokio::net
+                ::lookup_host((host, port)).await
+                .map_err(|err| err.to_string())?
+                .collect();
+
+            if addrs.is_empty() || addrs.iter().any(|addr| !is_public_ip(addr.ip())) {
+                return Err(format!("{} does not resolve to a public address", host));
+            }
+
+            builder.resolve_to_addrs(host, &addrs)
+        }
+    };
+
+    builder.build().map_err(|err| err.to_string())
+}
+
+impl CoverCache {
+    // Requested sizes are rounded up to one of these, so only a few variants get cached
+    const SIZES: [u32; 4] = [64, 128, 300, 600];
+    const MAX_ORIGINAL_BYTES: usize = 5 * 1024 * 1024;
+    const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+    const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+    const MAX_TOTAL_BYTES: u64 = 512 * 1024 * 1024;
+
+    fn snap_size(size: u32) -> u32 {
+        Self::SIZES.iter()
+            .copied()
+            .find(|bucket| *bucket >= size)
+            .unwrap_or(Self::SIZES[Self::SIZES.len() - 1])
+    }
+
+    // Drops files older than MAX_AGE, then the oldest ones until the cache fits in
+    // MAX_TOTAL_BYTES. Anything removed is simply fetched again on the next request.
+    async fn evict(&self) -> io::Result<()> {
+        let mut files = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                files.push((entry.path(), metadata.len(), metadata.modified()?));
+            }
+        }
+
+        files.sort_by_key(|(_, _, modified)| *modified);
+
+        let mut total: u64 = files
+            .iter()
+            .map(|(_, len, _)| len)
+            .sum();
+
+        for (path, len, modified) in files {
+            let expired = modified.elapsed().is_ok_and(|age| age > Self::MAX_AGE);
+            if !expired && total <= Self::MAX_TOTAL_BYTES {
+                break;
+            }
+
+            // Another request may have replaced the file in the meantime
+            match fs::remove_file(&path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e);
+                }
+                _ => (),
+            }
+            total -= len;
+        }
+
+        Ok(())
+    }
+
+    fn key(cover_url: &str) -> String {
+        format!("{:x}", Sha256::digest(cover_url.as_bytes()))
+    }
+
+    async fn write(&self, name: &str, bytes: &[u8]) -> io::Result<()> {
+        // Write to a temporary file first so readers never see a partial image
+        let path = self.dir.join(name);
+        let tmp = self.dir.join(format!("{}.{}.tmp", name, now_millis()));
+        fs::write(&tmp, bytes).await?;
+        fs::rename(&tmp, &path).await
+    }
+
+    // cover_url comes straight from the client, so only public https hosts are fetched
+    // and the body is capped
+    async fn get_original(&self, cover_url: &str) -> Result<Vec<u8>, String> {
+        let name = Self::key(cover_url);
+
+        if let Ok(bytes) = fs::read(self.dir.join(&name)).await {
+            return Ok(bytes);
+        }
+
+        let url = Url::parse(cover_url).map_err(|err| err.to_string())?;
+        if url.scheme() != "https" {
+            return Err(format!("Refusing to fetch non-https cover {}", cover_url));
+        }
+
+        let mut response = public_client(&url, Self::FETCH_TIMEOUT).await?
+            .get(url)
+            .send().await
+            .map_err(|err| err.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(format!("Upstream returned {}", response.status()));
+        }
+
+        let too_large = || format!("Cover is larger than {} bytes", Self::MAX_ORIGINAL_BYTES);
+
+        if response.content_length().is_some_and(|length| length > (Self::MAX_ORIGINAL_BYTES as u64)) {
+            return Err(too_large());
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+            if bytes.len() + chunk.len() > Self::MAX_ORIGINAL_BYTES {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        // Refuse to cache anything that isn't an image we can serve
+        image::guess_format(&bytes).map_err(|err| err.to_string())?;
+
+        self.write(&name, &bytes).await.map_err(|err| err.to_string())?;
+
+        Ok(bytes)
+    }
+
+    async fn get_resized(&self, cover_url: &str, size: u32) -> Result<Vec<u8>, String> {
+        let name = format!("{}_{}.jpg", Self::key(cover_url), size);
+
+        if let Ok(bytes) = fs::read(self.dir.join(&name)).await {
+            return Ok(bytes);
+        }
+
+        let original = self.get_original(cover_url).await?;
+
+        let bytes = task
+            ::spawn_blocking(move || -> Result<Vec<u8>, String> {
+                let image = image::load_from_memory(&original).map_err(|err| err.to_string())?;
+
+                // Only ever downscale, upscaling just wastes bandwidth
+                let image = if image.width() > size || image.height() > size {
+                    image.resize(size, size, FilterType::Lanczos3)
+                } else {
+                    image
+                };
+
+                let mut bytes = io::Cursor::new(Vec::new());
+                image
+                    .to_rgb8()
+                    .write_to(&mut bytes, ImageOutputFormat::Jpeg(85))
+                    .map_err(|err| err.to_string())?;
+
+                Ok(bytes.into_inner())
+            }).await
+            .map_err(|err| err.to_string())??;
+
+        self.write(&name, &bytes).await.map_err(|err| err.to_string())?;
+
+        Ok(bytes)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfMatch {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfMatch(req.headers().get_one("If-Match").map(String::from)))
+    }
+}
+
+impl IfMatch {
+    // Requests without the header aren't checked, so older clients keep working
+    fn matches(&self, version: u64) -> bool {
+        match self.0.as_deref() {
+            Some(header) => {
+                let etag = queue_etag(version);
+                header
+                    .split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || tag == etag)
+            }
+            None => true,
+        }
+    }
+}
+
+fn queue_etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+impl<'r, R: Responder<'r, 'static>> Responder<'r, 'static> for Versioned<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = self.inner.respond_to(req)?;
+        response.set_header(Header::new("ETag", queue_etag(self.version)));
+        Ok(response)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for CoverResponse {
+    fn respond_to(self, _req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        match self {
+            CoverResponse::Image { bytes, etag } => {
+                let content_type = match image::guess_format(&bytes) {
+                    Ok(ImageFormat::Png) => ContentType::PNG,
+                    Ok(ImageFormat::Gif) => ContentType::GIF,
+                    Ok(ImageFormat::WebP) => ContentType::WEBP,
+                    _ => ContentType::JPEG,
+                };
+
+                rocket::Response
+                    ::build()
+                    .header(content_type)
+                    .header(Header::new("ETag", etag))
+                    // The url stays the same when the song changes, so always revalidate
+                    .header(Header::new("Cache-Control", "no-cache"))
+                    .sized_body(bytes.len(), io::Cursor::new(bytes))
+                    .ok()
+            }
+            CoverResponse::NotModified { etag } =>
+                rocket::Response
+                    ::build()
+                    .status(Status::NotModified)
+                    .header(Header::new("ETag", etag))
+                    .ok(),
+        }
+    }
+}
+
+impl BatchSong {
+    const MAX_KEYS: usize = 50;
+
+    // Resolves every uuid and twitch name in a single round trip
+    pub async fn get_songs(
+        uuids: &[String],
+        names: &[String],
+        pool: &Pool<MySql>
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let placeholders = |count: usize| vec!["?"; count].join(", ");
+        let mut parts = Vec::new();
+
+        if !uuids.is_empty() {
+            parts.push(
+                format!(
+                    "SELECT sd.*, NULL AS lookup_name, cs.stale_after_secs
+                 FROM song_data sd
+                 LEFT JOIN channel_settings cs ON cs.uuid = sd.uuid
+                 WHERE sd.uuid IN ({})",
+                    placeholders(uuids.len())
+                )
+            );
+        }
+
+        if !names.is_empty() {
+            // Same "latest install wins" rule as the single lookup by name
+            parts.push(
+                format!(
+                    "SELECT sd.*, LOWER(su.twitch_name) AS lookup_name, cs.stale_after_secs
+                 FROM song_data sd
+                 JOIN songify_usage su ON sd.uuid = su.UUID
+                 LEFT JOIN channel_settings cs ON cs.uuid = sd.uuid
+                 WHERE LOWER(su.twitch_name) IN ({})
+                 AND su.tst = (
+                     SELECT MAX(tst)
+                     FROM songify_usage
+                     WHERE LOWER(twitch_name) = LOWER(su.twitch_name)
+                 )",
+                    placeholders(names.len())
+                )
+            );
+        }
+
+        if parts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let sql = parts.join(" UNION ALL ");
+        let mut query = sqlx::query(&sql);
+        for uuid in uuids {
+            query = query.bind(uuid);
+        }
+        for name in names {
+            query = query.bind(name.to_lowercase());
+        }
+
+        let rows = query.fetch_all(pool).await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(Self {
+                    song: Song::from_row(row)?,
+                    lookup_name: row.try_get("lookup_name")?,
+                    stale_after_secs: row.try_get("stale_after_secs")?,
+                })
+            })
+            .collect()
+    }
+
+    fn into_json(mut self) -> Value {
+        let settings = ChannelSettings {
+            stale_after_secs: self.stale_after_secs,
+            ..Default::default()
+        };
+        let state = self.song.state(settings.stale_after_ms());
+        self.song.position_ms = self.song.current_position();
+
+        let mut value = json!(self.song);
+        value["state"] = json!(state);
+        value
+    }
+}
+
+impl QueueStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            QueueStatus::Queued => "queued",
+            QueueStatus::Played => "played",
+            QueueStatus::Skipped => "skipped",
+            QueueStatus::Removed => "removed",
+            QueueStatus::Cleared => "cleared",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "queued" => Some(QueueStatus::Queued),
+            "played" => Some(QueueStatus::Played),
+            "skipped" => Some(QueueStatus::Skipped),
+            "removed" => Some(QueueStatus::Removed),
+            "cleared" => Some(QueueStatus::Cleared),
+            _ => None,
+        }
+    }
+}
+
+impl QueueRejection {
+    fn unprocessable(error: &'static str, message: String, limit: Option<usize>) -> QueueError {
+        QueueError::Rejected(QueueRejection {
+            status: Status::UnprocessableEntity,
+            error,
+            message,
+            limit,
+        })
+    }
+
+    fn forbidden(error: &'static str, message: String) -> QueueError {
+        QueueError::Rejected(QueueRejection {
+            status: Status::Forbidden,
+            error,
+            message,
+            limit: None,
+        })
+    }
+
+    fn too_many_requests(error: &'static str, message: String, limit: Option<usize>) -> QueueError {
+        QueueError::Rejected(QueueRejection {
+            status: Status::TooManyRequests,
+            error,
+            message,
+            limit,
+        })
+    }
+
+    fn conflict(error: &'static str, message: String, limit: Option<usize>) -> QueueError {
+        QueueError::Rejected(QueueRejection {
+            status: Status::Conflict,
+            error,
+            message,
+            limit,
+        })
+    }
+}
+
+impl From<sqlx::Error> for QueueError {
+    fn from(err: sqlx::Error) -> Self {
+        QueueError::Database(err)
+    }
+}
+
+impl From<Status> for QueueError {
+    fn from(status: Status) -> Self {
+        QueueError::Status(status)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for QueueError {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        match self {
+            QueueError::Rejected(rejection) => {
+                let status = rejection.status;
+                (status, Json(rejection)).respond_to(req)
+            }
+            QueueError::Status(status) => Err(status),
+            QueueError::Database(e) => {
+                println!("❌ SQL Error: {}", e);
+                Err(Status::InternalServerError)
+            }
+        }
+    }
+}
+
+impl QueueSong {
+    const MAX_IMPORT_ITEMS: usize = 500;
+    // How long a cleared queue can still be restored
+    const RESTORE_WINDOW_MS: i64 = 15 * 60 * 1000;
+
+    pub async fn get_queue(
+        param: QueueParam,
+        pool: &Pool<MySql>
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let query = match param {
+            QueueParam::Id(id) => {
+                sqlx::query_as::<MySql, Self>(
+                    "SELECT * FROM songify_queue WHERE Uuid = ? AND Played = 0 ORDER BY Priority DESC, Position ASC, Queueid ASC;"
+                ).bind(id)
+            }
+            QueueParam::Name(name) => {
+                sqlx::query_as::<MySql, Self>(
+                    "
+                SELECT sq.*
+                FROM songify_queue sq
+                JOIN (
+                    SELECT UUID
+                    FROM songify_usage
+                    WHERE LOWER(twitch_name) = LOWER(?)
+                    ORDER BY tst DESC
+                    LIMIT 1
+                ) su ON sq.Uuid = su.UUID
+                WHERE sq.played = 0
+                ORDER BY sq.Priority DESC, sq.Position ASC, sq.Queueid ASC;"
+                ).bind(name)
+            }
+        };
+
+        let mut queue = query.fetch_all(pool).await?;
+
+        // Length is the source of truth, rows stored before the server parsed it may
+        // carry a missing or client-supplied LengthMs
+        for song in &mut queue {
+            song.LengthMs = parse_length_ms(&song.Length);
+        }
+
+        Ok(queue)
+    }
+
+    // Requests that only carry a raw "Artist - Title" string get split into both
+    // fields, and the free-form Length is parsed so limits can be checked. A LengthMs sent
+    // by the client is ignored, it could understate the length to get past the limit.
+    pub fn normalize(&mut self) {
+        self.LengthMs = parse_length_ms(&self.Length);
+
+        if !self.Artist.is_empty() {
+            return;
+        }
+
+        if let (Some(artist), Some(title)) = parse_song_string(&self.Title, true) {
+            self.Artist = artist;
+            self.Title = title;
+        }
+    }
+
+    pub async fn add_to_queue(
+        id: String,
+        mut song: Self,
+        if_match: &IfMatch,
+        pool: &Pool<MySql>
+    ) -> Result<(Self, u64), QueueError> {
+        song.normalize();
+
+        let settings = ChannelSettings::get_settings(id.clone(), pool).await?;
+
+        let mut tx = pool.begin().await?;
+        if !if_match.matches(Self::lock_version(&id, &mut tx).await?) {
+            return Err(QueueError::Status(Status::PreconditionFailed));
+        }
+
+        Self::check_request(&id, &song, &settings, &mut tx).await?;
+        let inserted_song = Self::insert(&id, &song, &mut tx).await?;
+        let version = Self::bump_version(&id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok((inserted_song, version))
+    }
+
+    // Every change to a channel's queue bumps its version, which is served as the ETag.
+    // Taking the version row lock first keeps the lock order the same everywhere.
+    pub async fn get_version(uuid: &str, pool: &Pool<MySql>) -> sqlx::Result<u64> {
+        let version: Option<u64> = sqlx
+            ::query_scalar("SELECT Version FROM songify_queue_versions WHERE Uuid = ?")
+            .bind(uuid)
+            .fetch_optional(pool).await?;
+
+        Ok(version.unwrap_or(0))
+    }
+
+    async fn lock_version(uuid: &str, tx: &mut Transaction<'_, MySql>) -> sqlx::Result<u64> {
+        sqlx
+            ::query(
+                "INSERT INTO songify_queue_versions (Uuid, Version) VALUES (?, 0) ON DUPLICATE KEY UPDATE Version = Version"
+            )
+            .bind(uuid)
+            .execute(&mut *tx).await?;
+
+        sqlx
+            ::query_scalar("SELECT Version FROM songify_queue_versions WHERE Uuid = ? FOR UPDATE")
+            .bind(uuid)
+            .fetch_one(&mut *tx).await
+    }
+
+    async fn bump_version(uuid: &str, tx: &mut Transaction<'_, MySql>) -> sqlx::Result<u64> {
+        sqlx
+            ::query("UPDATE songify_queue_versions SET Version = Version + 1 WHERE Uuid = ?")
+            .bind(uuid)
+            .execute(&mut *tx).await?;
+
+        sqlx
+            ::query_scalar("SELECT Version FROM songify_queue_versions WHERE Uuid = ?")
+            .bind(uuid)
+            .fetch_one(&mut *tx).await
+    }
+
+    // Restores a saved queue in one go. Every item goes through the same checks as a
+    // single request, and one rejection rolls the whole import back.
+    pub async fn import_queue(
+        id: String,
+        songs: Vec<Self>,
+        pool: &Pool<MySql>
+    ) -> Result<Vec<Self>, QueueError> {
+        if songs.len() > Self::MAX_IMPORT_ITEMS {
+            return Err(
+                QueueRejection::unprocessable(
+                    "import_too_large",
+                    format!("At most {} items can be imported at once", Self::MAX_IMPORT_ITEMS),
+                    Some(Self::MAX_IMPORT_ITEMS)
+                )
+            );
+        }
+
+        let settings = ChannelSettings::get_settings(id.clone(), pool).await?;
+
+        let mut tx = pool.begin().await?;
+        Self::lock_version(&id, &mut tx).await?;
+        let mut inserted = Vec::with_capacity(songs.len());
+
+        for (index, mut song) in songs.into_iter().enumerate() {
+            song.normalize();
+
+            Self::check_request(&id, &song, &settings, &mut tx).await.map_err(|err| {
+                match err {
+                    QueueError::Rejected(mut rejection) => {
+                        rejection.message = format!("Item {}: {}", index, rejection.message);
+                        QueueError::Rejected(rejection)
+                    }
+                    err => err,
+                }
+            })?;
+            inserted.push(Self::insert(&id, &song, &mut tx).await?);
+        }
+
+        Self::bump_version(&id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(inserted)
+    }
+
+    // Enforces the channel's queue rules. The unplayed rows are locked until the
+    // transaction ends, so concurrent requests can't slip past a limit together.
+    async fn check_request(
+        id: &str,
+        song: &Self,
+        settings: &ChannelSettings,
+        tx: &mut Transaction<'_, MySql>
+    ) -> Result<(), QueueError> {
+        let queue: Vec<(String, String)> = sqlx
+            ::query_as(
+                "SELECT Requester, Trackid FROM songify_queue WHERE Uuid = ? AND Played = 0 FOR UPDATE"
+            )
+            .bind(id)
+            .fetch_all(&mut *tx).await?;
+
+        let blocklist = sqlx
+            ::query_as::<MySql, BlocklistEntry>("SELECT * FROM songify_blocklist WHERE uuid = ?")
+            .bind(id)
+            .fetch_all(&mut *tx).await?;
+
+        if let Some(entry) = blocklist.iter().find(|entry| entry.matches(song)) {
+            return Err(QueueRejection::forbidden("blocked", entry.rejection_message(song)));
+        }
+
+        if !song.Requester.is_empty() {
+            Self::check_requester(id, &song.Requester, settings, tx).await?;
+        }
+
+        // Songs without a parseable length can't be checked and are let through
+        if let Some(max) = ChannelSettings::limit(settings.max_song_length_secs) {
+            if song.LengthMs.is_some_and(|length| length > (max as i64) * 1000) {
+                return Err(
+                    QueueRejection::unprocessable(
+                        "too_long",
+                        format!("{} - {} is longer than {}", song.Artist, song.Title, format_length(max)),
+                        Some(max)
+                    )
+                );
+            }
+        }
+
+        let queued = queue.iter().any(|(_, trackid)| *trackid == song.Trackid);
+        if queued && settings.reject_queued_duplicates.unwrap_or(false) {
+            return Err(
+                QueueRejection::conflict(
+                    "duplicate_in_queue",
+                    format!("{} - {} is already in the queue", song.Artist, song.Title),
+                    None
+                )
+            );
+        }
+
+        if let Some(minutes) = ChannelSettings::limit(settings.replay_cooldown_mins) {
+            if History::played_since(id, song, minutes, &mut *tx).await? {
+                return Err(
+                    QueueRejection::conflict(
+                        "recently_played",
+                        format!(
+                            "{} - {} was played in the last {} minutes",
+                            song.Artist,
+                            song.Title,
+                            minutes
+                        ),
+                        Some(minutes)
+                    )
+                );
+            }
+        }
+
+        if let Some(max) = ChannelSettings::limit(settings.max_queue_length) {
+            if queue.len() >= max {
+                return Err(
+                    QueueRejection::conflict(
+                        "queue_full",
+                        format!("The queue is full ({} songs)", max),
+                        Some(max)
+                    )
+                );
+            }
+        }
+
+        if let Some(max) = ChannelSettings::limit(settings.max_requests_per_user) {
+            let open = queue
+                .iter()
+                .filter(|(requester, _)| requester.eq_ignore_ascii_case(&song.Requester))
+                .count();
+
+            if !song.Requester.is_empty() && open >= max {
+                return Err(
+                    QueueRejection::conflict(
+                        "requester_limit",
+                        format!("{} already has {} songs in the queue", song.Requester, open),
+                        Some(max)
+                    )
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    // Bans and the per-requester cooldown. The cooldown counts from the requester's
+    // last accepted request, taken from the status log.
+    async fn check_requester(
+        id: &str,
+        requester: &str,
+        settings: &ChannelSettings,
+        tx: &mut Transaction<'_, MySql>
+    ) -> Result<(), QueueError> {
+        let now = now_millis();
+
+        let banned: Option<Option<String>> = sqlx
+            ::query_scalar(
+                "SELECT reason FROM songify_requester_bans WHERE uuid = ? AND requester = ? AND (expires_at IS NULL OR expires_at > ?)"
+            )
+            .bind(id)
+            .bind(requester)
+            .bind(now)
+            .fetch_optional(&mut *tx).await?;
+
+        if let Some(reason) = banned {
+            let message = match reason {
+                Some(reason) => format!("{} is banned from requesting songs: {}", requester, reason),
+                None => format!("{} is banned from requesting songs", requester),
+            };
+            return Err(QueueRejection::forbidden("banned", message));
+        }
+
+        if let Some(cooldown) = ChannelSettings::limit(settings.requester_cooldown_secs) {
+            let last: Option<i64> = sqlx
+                ::query_scalar(
+                    "SELECT MAX(CreatedAt) FROM songify_queue_status WHERE Uuid = ? AND Status = ? AND Actor = ?"
+                )
+                .bind(id)
+                .bind(QueueStatus::Queued.as_str())
+                .bind(requester)
+                .fetch_one(&mut *tx).await?;
+
+            let wait_ms = last.map_or(0, |last| last + (cooldown as i64) * 1000 - now);
+            if wait_ms > 0 {
+                return Err(
+                    QueueRejection::too_many_requests(
+                        "requester_cooldown",
+                        format!(
+                            "{} can request again in {}",
+                            requester,
+                            format_length(((wait_ms + 999) / 1000) as usize)
+                        ),
+                        Some(cooldown)
+                    )
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn insert(
+        id: &str,
+        song: &Self,
+        tx: &mut Transaction<'_, MySql>
+    ) -> sqlx::Result<Self> {
+        // New requests go to the end of their priority lane
+        let inserted_song = sqlx
+            ::query_as::<MySql, Self>(
+                "INSERT INTO songify_queue (Queueid, Uuid, Trackid, Artist, Title, Length, Requester, Played, Albumcover, Priority, LengthMs, Status, StatusAt, StatusBy, Position) 
+            SELECT NULL, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, COALESCE(MAX(Position), 0) + 1 FROM songify_queue WHERE Uuid = ? 
+            RETURNING *"
+            )
+            .bind(id)
+            .bind(&song.Trackid)
+            .bind(&song.Artist)
+            .bind(&song.Title)
+            .bind(&song.Length)
+            .bind(&song.Requester)
+            .bind(0)
+            .bind(&song.Albumcover)
+            .bind(song.Priority.unwrap_or(0))
+            .bind(song.LengthMs)
+            .bind(QueueStatus::Queued.as_str())
+            .bind(now_millis())
+            .bind(&song.Requester)
+            .bind(id)
+            .fetch_one(&mut *tx).await?;
+
+        if let Some(queueid) = inserted_song.Queueid {
+            Self::log_status(id, queueid, QueueStatus::Queued, &song.Requester, tx).await?;
+        }
+
+        Ok(inserted_song)
+    }
+
+    async fn log_status(
+        uuid: &str,
+        queueid: i32,
+        status: QueueStatus,
+        actor: &str,
+        tx: &mut Transaction<'_, MySql>
+    ) -> sqlx::Result<()> {
+        sqlx
+            ::query(
+                "INSERT INTO songify_queue_status (Queueid, Uuid, Status, Actor, CreatedAt) VALUES (?, ?, ?, ?, ?)"
+            )
+            .bind(queueid)
+            .bind(uuid)
+            .bind(status.as_str())
+            .bind(actor)
+            .bind(now_millis())
+            .execute(&mut *tx).await?;
+
+        Ok(())
+    }
+
+    // Moves an unplayed item to `index` and renumbers the rest of the queue. The
+    // rows stay locked until commit, so concurrent moves are applied one after another.
+    // An item moved past another priority lane joins the lane it lands in.
+    pub async fn move_in_queue(
+        uuid: String,
+        queueid: i32,
+        index: usize,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<Option<usize>> {
+        let mut tx = pool.begin().await?;
+        Self::lock_version(&uuid, &mut tx).await?;
+
+        let mut queue: Vec<(i32, i32)> = sqlx
+            ::query_as(
+                "SELECT Queueid, COALESCE(Priority, 0) FROM songify_queue WHERE Uuid = ? AND Played = 0 ORDER BY Priority DESC, Position ASC, Queueid ASC FOR UPDATE"
+            )
+            .bind(&uuid)
+            .fetch_all(&mut tx).await?;
+
+        let current = match queue.iter().position(|(id, _)| *id == queueid) {
+            Some(current) => current,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        let (_, priority) = queue.remove(current);
+        let index = index.min(queue.len());
+
+        // The queue is sorted by priority, so the neighbours bound the lane at `index`
+        let upper = index.checked_sub(1).map_or(i32::MAX, |before| queue[before].1);
+        let lower = queue.get(index).map_or(i32::MIN, |(_, after)| *after);
+        queue.insert(index, (queueid, priority.clamp(lower, upper)));
+
+        for (position, (id, priority)) in queue.iter().enumerate() {
+            sqlx
+                ::query(
+                    "UPDATE songify_queue SET Position = ?, Priority = ? WHERE Uuid = ? AND Queueid = ?"
+                )
+                .bind((position + 1) as i32)
+                .bind(priority)
+                .bind(&uuid)
+                .bind(id)
+                .execute(&mut tx).await?;
+        }
+
+        Self::bump_version(&uuid, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(Some(index))
+    }
+
+    // `Played` stays set for every status other than queued, older clients only look at that.
+    // Returns the new queue version, or None when `if_match` is out of date.
+    pub async fn remove_from_queue(
+        uuid: String,
+        queueid: i32,
+        status: QueueStatus,
+        actor: String,
+        if_match: &IfMatch,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<Option<u64>> {
+        let mut tx = pool.begin().await?;
+
+        let mut version = Self::lock_version(&uuid, &mut tx).await?;
+        if !if_match.matches(version) {
+            return Ok(None);
+        }
+
+        let result = sqlx
+            ::query(
+                "UPDATE songify_queue SET Played = 1, Status = ?, StatusAt = ?, StatusBy = ? WHERE Uuid = ? AND Queueid = ? AND Played = 0"
+            )
+            .bind(status.as_str())
+            .bind(now_millis())
+            .bind(&actor)
+            .bind(&uuid)
+            .bind(queueid)
+            .execute(&mut tx).await?;
+
+        if result.rows_affected() > 0 {
+            Self::log_status(&uuid, queueid, status, &actor, &mut tx).await?;
+            version = Self::bump_version(&uuid, &mut tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(version))
+    }
+
+    // Each clear is recorded as a batch so it can be undone with `restore_queue`.
+    // Returns the new queue version, or None when `if_match` is out of date.
+    pub async fn clear_queue(
+        uuid: String,
+        actor: String,
+        if_match: &IfMatch,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<Option<u64>> {
+        let mut tx = pool.begin().await?;
+        let now = now_millis();
+
+        let version = Self::lock_version(&uuid, &mut tx).await?;
+        if !if_match.matches(version) {
+            return Ok(None);
+        }
+
+        let batch = sqlx
+            ::query("INSERT INTO songify_queue_clears (Uuid, Actor, CreatedAt) VALUES (?, ?, ?)")
+            .bind(&uuid)
+            .bind(&actor)
+            .bind(now)
+            .execute(&mut tx).await?
+            .last_insert_id();
+
+        let cleared = sqlx
+            ::query(
+                "UPDATE songify_queue SET Played = 1, Status = ?, StatusAt = ?, StatusBy = ?, ClearId = ? WHERE Uuid = ? AND Played = 0"
+            )
+            .bind(QueueStatus::Cleared.as_str())
+            .bind(now)
+            .bind(&actor)
+            .bind(batch)
+            .bind(&uuid)
+            .execute(&mut tx).await?
+            .rows_affected();
+
+        // Nothing to undo, dropping the transaction discards the empty batch
+        if cleared == 0 {
+            return Ok(Some(version));
+        }
+
+        sqlx
+            ::query(
+                "INSERT INTO songify_queue_status (Queueid, Uuid, Status, Actor, CreatedAt) 
+            SELECT Queueid, Uuid, ?, ?, ? FROM songify_queue WHERE ClearId = ?"
+            )
+            .bind(QueueStatus::Cleared.as_str())
+            .bind(&actor)
+            .bind(now)
+            .bind(batch)
+            .execute(&mut tx).await?;
+
+        let version = Self::bump_version(&uuid, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(Some(version))
+    }
+
+    // Puts the most recent clear back if it's still inside the restore window. Items whose
+    // song was played or queued again since then stay cleared. Returns None without a batch.
+    pub async fn restore_queue(
+        uuid: String,
+        actor: String,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<Option<Vec<Self>>> {
+        let mut tx = pool.begin().await?;
+        let now = now_millis();
+        Self::lock_version(&uuid, &mut tx).await?;
+
+        let batch: Option<(u64, i64)> = sqlx
+            ::query_as(
+                "SELECT Id, CreatedAt FROM songify_queue_clears WHERE Uuid = ? AND RestoredAt IS NULL AND CreatedAt >= ? ORDER BY Id DESC LIMIT 1 FOR UPDATE"
+            )
+            .bind(&uuid)
+            .bind(now - Self::RESTORE_WINDOW_MS)
+            .fetch_optional(&mut tx).await?;
+
+        let Some((batch, cleared_at)) = batch else {
+            return Ok(None);
+        };
+
+        let items = sqlx
+            ::query_as::<MySql, Self>(
+                "SELECT * FROM songify_queue WHERE Uuid = ? AND ClearId = ? AND Status = ? ORDER BY Priority DESC, Position ASC, Queueid ASC FOR UPDATE"
+            )
+            .bind(&uuid)
+            .bind(batch)
+            .bind(QueueStatus::Cleared.as_str())
+            .fetch_all(&mut tx).await?;
+
+        // Tracks that were played through the queue or are waiting in it again
+        let taken: Vec<String> = sqlx
+            ::query_scalar(
+                "SELECT Trackid FROM songify_queue WHERE Uuid = ? AND (Played = 0 OR (Status = ? AND StatusAt >= ?))"
+            )
+            .bind(&uuid)
+            .bind(QueueStatus::Played.as_str())
+            .bind(cleared_at)
+            .fetch_all(&mut tx).await?;
+
+        let minutes = ((now - cleared_at) / 60_000 + 1) as usize;
+        let mut restored = Vec::with_capacity(items.len());
+
+        for mut song in items {
+            let Some(queueid) = song.Queueid else {
+                continue;
+            };
+
+            if !song.Trackid.is_empty() && taken.contains(&song.Trackid) {
+                continue;
+            }
+
+            if History::played_since(&uuid, &song, minutes, &mut tx).await? {
+                continue;
+            }
+
+            sqlx
+                ::query(
+                    "UPDATE songify_queue SET Played = 0, Status = ?, StatusAt = ?, StatusBy = ?, ClearId = NULL WHERE Queueid = ?"
+                )
+                .bind(QueueStatus::Queued.as_str())
+                .bind(now)
+                .bind(&actor)
+                .bind(queueid)
+                .execute(&mut tx).await?;
+
+            Self::log_status(&uuid, queueid, QueueStatus::Queued, &actor, &mut tx).await?;
+
+            song.Played = 0;
+            song.Status = Some(QueueStatus::Queued.as_str().to_string());
+            song.StatusAt = Some(now);
+            song.StatusBy = Some(actor.clone());
+            restored.push(song);
+        }
+
+        sqlx
+            ::query("UPDATE songify_queue_clears SET RestoredAt = ?, RestoredBy = ? WHERE Id = ?")
+            .bind(now)
+            .bind(&actor)
+            .bind(batch)
+            .execute(&mut tx).await?;
+
+        if !restored.is_empty() {
+            Self::bump_version(&uuid, &mut tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(restored))
+    }
+
+    // Items that have left the queue, newest first
+    pub async fn get_queue_history(
+        uuid: String,
+        status: Option<QueueStatus>,
+        limit: u32,
+        pool: &Pool<MySql>
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let query = match status {
+            Some(status) =>
+                sqlx
+                    ::query_as::<MySql, Self>(
+                        "SELECT * FROM songify_queue WHERE Uuid = ? AND Played = 1 AND Status = ? ORDER BY StatusAt DESC, Queueid DESC LIMIT ?"
+                    )
+                    .bind(uuid)
+                    .bind(status.as_str()),
+            None =>
+                sqlx
+                    ::query_as::<MySql, Self>(
+                        "SELECT * FROM songify_queue WHERE Uuid = ? AND Played = 1 ORDER BY StatusAt DESC, Queueid DESC LIMIT ?"
+                    )
+                    .bind(uuid),
+        };
+
+        query.bind(limit).fetch_all(pool).await
+    }
+}
+
+impl Usage {
+    pub async fn get_access_key(
+        id: String,
+        pool: &Pool<MySql>
+    ) -> Result<Option<String>, sqlx::Error> {
+        let usage = sqlx
+            ::query("SELECT access_key FROM songify_usage WHERE UUID = ?")
+            .bind(id)
+            .fetch_one(pool).await;
+
+        match usage {
+            Ok(usage) => Ok(Some(usage.get(0))),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => { Err(e) }
+        }
+    }
+
+    pub async fn set_access_key(
+        id: String,
+        access_key: String,
+        pool: &Pool<MySql>
+    ) -> sqlx::Result<()> {
+        sqlx
+            ::query("UPDATE songify_usage SET access_key = ? WHERE UUID = ?")
+            .bind(access_key)
+            .bind(id)
+            .execute(pool).await?;
+
+        Ok(())
+    }
+
+    pub async fn set_telemetry(telemetry: Telemetry, pool: &Pool<MySql>) -> sqlx::Result<()> {
+        if telemetry.uuid.is_empty() {
+            return Err(
+                (ValidationError {
+                    message: "UUID cannot be empty".to_string(),
+                }).into()
+            );
+        }
+        log_unknown_player_type(&telemetry.uuid, &PlayerType::parse(&telemetry.playertype));
+
+        sqlx
+            ::query(
+                "REPLACE INTO songify_usage (UUID, tst, twitch_id, twitch_name, vs, playertype, access_key) VALUES (?, ?, ?, ?, ?, ?, ?)"
+            )
+            .bind(telemetry.uuid)
+            .bind(telemetry.tst.to_string())
+            .bind(telemetry.twitch_id)
+            .bind(telemetry.twitch_name)
+            .bind(telemetry.vs)
+            .bind(telemetry.playertype)
+            .bind(telemetry.key)
+            .execute(pool).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_uuid(
+        param: QueueParam,
+        pool: &Pool<MySql>
+    ) -> Result<Option<String>, sqlx::Error> {
+        let name = match param {
+            QueueParam::Id(id) => {
+                return Ok(Some(id));
+            }
+            QueueParam::Name(name) => name,
+        };
+
+        let usage = sqlx
+            ::query(
+                "SELECT UUID FROM songify_usage WHERE LOWER(twitch_name) = LOWER(?) ORDER BY tst DESC LIMIT 1"
+            )
+            .bind(name)
+            .fetch_one(pool).await;
+
+        match usage {
+            Ok(usage) => Ok(Some(usage.get(0))),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => { Err(e) }
+        }
+    }
+
+    pub async fn get_twitch_name(
+        id: String,
+        pool: &Pool<MySql>
+    ) -> Result<Option<String>, sqlx::Error> {
+        let usage = sqlx
+            ::query("SELECT twitch_name FROM songify_usage WHERE UUID = ?")
+            .bind(id)
+            .fetch_one(pool).await;
+
+        match usage {
+            Ok(usage) => Ok(Some(usage.get(0))),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => { Err(e) }
+        }
+    }
+}
+
+async fn verify_access_key(
+    uuid: &str,
+    api_key: &str,
+    pool: &State<Pool<MySql>>
+) -> Result<(), Status> {
+    let access_key = Usage::get_access_key(uuid.to_string(), pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+
+    match access_key {
+        Some(key) => {
+            if key != api_key {
+                println!("Access key mismatch");
+                println!("{} != {}", key, api_key);
+                return Err(Status::Unauthorized);
+            }
+        }
+        None => {
+            Usage::set_access_key(uuid.to_string(), api_key.to_string(), pool).await.map_err(
+                |_| Status::InternalServerError
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+enum SongResponse {
+    Json(Json<Value>),
+    Plain(RawText<String>),
+    Xml(RawXml<String>),
+}
+
+impl<'r> Responder<'r, 'static> for SongResponse {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        match self {
+            SongResponse::Json(json) => json.respond_to(req),
+            SongResponse::Plain(text) => text.respond_to(req),
+            SongResponse::Xml(xml) => xml.respond_to(req),
+        }
+    }
+}
+
+enum SongFormat {
+    Plain,
+    Json,
+    Xml,
+}
+
+impl SongFormat {
+    // Picks the best type we can serve from the Accept header, None if none of them is acceptable
+    fn negotiate(accept: Option<&Accept>) -> Option<Self> {
+        let accept = match accept {
+            Some(accept) => accept,
+            None => {
+                return Some(SongFormat::Plain);
+            }
+        };
+
+        // Highest weight each format is explicitly named with. Wildcards only say that
+        // plain text is acceptable, their weight is ignored.
+        let (mut plain, mut json, mut xml) = (None::<f32>, None::<f32>, None::<f32>);
+        let mut wildcard = false;
+
+        for media_type in accept.iter() {
+            let weight = media_type.weight_or(1.0);
+            if weight <= 0.0 {
+                continue;
+            }
+
+            let (top, sub) = (media_type.top().as_str(), media_type.sub().as_str());
+            let best = match (top.to_lowercase().as_str(), sub.to_lowercase().as_str()) {
+                ("*", "*") | ("text", "*") => {
+                    wildcard = true;
+                    continue;
+                }
+                ("text", "plain") => &mut plain,
+                ("application", "json") => &mut json,
+                ("application", "xml") | ("text", "xml") => &mut xml,
+                _ => {
+                    continue;
+                }
+            };
+            *best = Some(best.map_or(weight, |best| best.max(weight)));
+        }
+
+        // Plain text wins unless a structured type is named and ranks above text/plain itself,
+        // so browsers ("application/xml;q=0.9,*/*;q=0.8") and clients sending
+        // "application/json, text/plain, */*" keep getting the response they always got
+        let structured = match (json, xml) {
+            (Some(json), Some(xml)) if xml > json => Some((SongFormat::Xml, xml)),
+            (Some(json), _) => Some((SongFormat::Json, json)),
+            (None, Some(xml)) => Some((SongFormat::Xml, xml)),
+            (None, None) => None,
+        };
+
+        match (plain, structured) {
+            (Some(plain), Some((format, weight))) if weight > plain => Some(format),
+            (Some(_), _) => Some(SongFormat::Plain),
+            (None, _) if wildcard => Some(SongFormat::Plain),
+            (None, structured) => structured.map(|(format, _)| format),
+        }
+    }
+}
+
+// Column order matches the JSON export minus the bookkeeping fields
+fn queue_to_csv(queue: &[QueueSong]) -> String {
+    let mut csv = String::from(
+        "queueid,trackid,artist,title,length,length_ms,requester,priority,position,albumcover\r\n"
+    );
+
+    for song in queue {
+        let row = [
+            song.Queueid.map(|id| id.to_string()).unwrap_or_default(),
+            song.Trackid.clone(),
+            song.Artist.clone(),
+            song.Title.clone(),
+            song.Length.clone(),
+            song.LengthMs.map(|ms| ms.to_string()).unwrap_or_default(),
+            song.Requester.clone(),
+            song.Priority.unwrap_or(0).to_string(),
+            song.Position.map(|position| position.to_string()).unwrap_or_default(),
+            song.Albumcover.clone().unwrap_or_default(),
+        ];
+
+        let fields: Vec<String> = row.iter().map(|field| escape_csv(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+
+    csv
+}
+
+fn escape_csv(value: &str) -> String {
+    // Requesters control titles, so keep spreadsheets from reading a cell as a formula
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+// Flat <song> document for legacy widgets, one element per JSON field
+fn song_to_xml(song: &Value) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<song>");
+
+    if let Some(fields) = song.as_object() {
+        for (key, value) in fields {
+            let text = match value {
+                Value::Null => String::new(),
+                Value::String(value) => escape_html(value),
+                value => value.to_string(),
+            };
+            xml.push_str(&format!("<{}>{}</{}>", key, text, key));
+        }
+    }
+
+    xml.push_str("</song>");
+    xml
+}
+
+#[get("/getsong?<params..>")]
+async fn get_song(
+    pool: &State<Pool<MySql>>,
+    params: QueueParams,
+    accept: Option<&Accept>
+) -> Result<SongResponse, Status> {
+    let param = if let Some(uuid) = params.uuid {
+        QueueParam::Id(uuid)
+    } else if let Some(name) = params.name {
+        QueueParam::Name(name)
+    } else {
+        return Err(Status::BadRequest);
+    };
+
+    // `full` predates content negotiation and keeps forcing JSON for existing clients
+    let format = if params.full.unwrap_or(false) {
+        SongFormat::Json
+    } else {
+        SongFormat::negotiate(accept).ok_or(Status::NotAcceptable)?
+    };
+
+    let song = Song::get_song(param.clone(), pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+
+    // Without a song row the channel (and its settings) may still exist
+    let uuid = match &song {
+        Some(song) => Some(song.uuid.clone()),
+        None => Usage::get_uuid(param.clone(), pool).await.map_err(|_| Status::InternalServerError)?,
+    };
+    let settings = match uuid {
+        Some(uuid) =>
+            ChannelSettings::get_settings(uuid, pool).await.map_err(
+                |_| Status::InternalServerError
+            )?,
+        None => ChannelSettings::default(),
+    };
+
+    let state = song.as_ref().map_or(PlaybackState::Empty, |song| {
+        song.state(settings.stale_after_ms())
+    });
+    let mut song = song.unwrap_or_else(|| {
+        let id_or_name = match param {
+            QueueParam::Id(id) => id,
+            QueueParam::Name(name) => name,
+        };
+        Song::empty(id_or_name, settings.idle_text())
+    });
+
+    match format {
+        SongFormat::Json | SongFormat::Xml => {
+            song.position_ms = song.current_position();
+
+            // `playertype` stays the raw value, `player` is the normalized name
+            let player_type = song.player_type();
+            let mut value = json!(song);
+            value["state"] = json!(state);
+            value["player"] = json!(player_type.as_ref().map(PlayerType::to_string));
+            value["known_playertype"] = json!(player_type.is_some_and(|player_type| player_type.is_known()));
+
+            return Ok(match format {
+                SongFormat::Xml => SongResponse::Xml(RawXml(song_to_xml(&value))),
+                _ => SongResponse::Json(Json(value)),
+            });
+        }
+        SongFormat::Plain => (),
+    }
+
+    if matches!(state, PlaybackState::Stale | PlaybackState::Empty) {
+        return Ok(SongResponse::Plain(RawText(settings.idle_text())));
+    }
+
+    // A one-off ?format= wins over the template stored for the channel
+    let template = params.format.or(settings.song_template);
+
+    match template.filter(|template| !template.is_empty()) {
+        Some(template) => Ok(SongResponse::Plain(RawText(song.render(&template)))),
+        None => Ok(SongResponse::Plain(RawText(song.song))),
+    }
+}
+
+#[get("/getcover?<params..>")]
+async fn get_cover(pool: &State<Pool<MySql>>, params: QueueParams) -> Result<String, Status> {
+    let param = if let Some(uuid) = params.uuid {
+        QueueParam::Id(uuid)
+    } else if let Some(name) = params.name {
+        QueueParam::Name(name)
+    } else {
+        return Err(Status::BadRequest);
+    };
+
+    Song::get_song(param, pool).await.map_or(Err(Status::InternalServerError), |song|
+        Ok(song.map_or_else(String::new, |song| song.cover_url))
+    )
+}
+
+#[get("/getsongs?<params..>")]
+async fn get_songs(pool: &State<Pool<MySql>>, params: BatchSongParams) -> Result<Value, Status> {
+    let mut uuids = params.uuid;
+    let mut names: Vec<String> = params.name
+        .iter()
+        .map(|name| name.to_lowercase())
+        .collect();
+    uuids.sort();
+    uuids.dedup();
+    names.sort();
+    names.dedup();
+
+    if uuids.is_empty() && names.is_empty() {
+        return Err(Status::BadRequest);
+    }
+    if uuids.len() + names.len() > BatchSong::MAX_KEYS {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    let rows = BatchSong::get_songs(&uuids, &names, pool).await.map_err(|e| {
+        eprintln!("Error fetching songs: {:?}", e);
+        Status::InternalServerError
+    })?;
+
+    // Every requested key gets an entry, so one missing channel doesn't fail the rest
+    let missing = json!({ "state": PlaybackState::Empty, "error": "No song found" });
+    let mut by_uuid: serde_json::Map<String, Value> = uuids
+        .iter()
+        .map(|uuid| (uuid.clone(), missing.clone()))
+        .collect();
+    let mut by_name: serde_json::Map<String, Value> = names
+        .iter()
+        .map(|name| (name.clone(), missing.clone()))
+        .collect();
+
+    for row in rows {
+        match row.lookup_name.clone() {
+            Some(name) => by_name.insert(name, row.into_json()),
+            None => by_uuid.insert(row.song.uuid.clone(), row.into_json()),
+        };
+    }
+
+    Ok(json!({
+        "uuids": by_uuid,
+        "names": by_name,
+    }))
+}
+
+#[get("/songlink?<params..>")]
+async fn get_song_link(
+    pool: &State<Pool<MySql>>,
+    params: QueueParams
+) -> Result<SongResponse, Status> {
+    let param = if let Some(uuid) = params.uuid {
+        QueueParam::Id(uuid)
+    } else if let Some(name) = params.name {
+        QueueParam::Name(name)
+    } else {
+        return Err(Status::BadRequest);
+    };
+
+    let song = Song::get_song(param, pool).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let player_type = song.player_type().unwrap_or(PlayerType::Unknown(String::new()));
+    let url = song.song_id.as_deref().and_then(|song_id| player_type.track_url(song_id));
+
+    if params.full.unwrap_or(false) {
+        return Ok(
+            SongResponse::Json(
+                Json(
+                    json!({
+                "url": url,
+                "song_id": song.song_id,
+                "playertype": player_type.to_string(),
+                "known_playertype": player_type.is_known(),
+            })
+                )
+            )
+        );
+    }
+
+    url.map_or(Err(Status::NotFound), |url| Ok(SongResponse::Plain(RawText(url))))
+}
+
+#[get("/cover/<file>?<size>")]
+async fn get_cover_image(
+    pool: &State<Pool<MySql>>,
+    cache: &State<CoverCache>,
+    file: &str,
+    size: Option<u32>,
+    if_none_match: IfNoneMatch
+) -> Result<CoverResponse, Status> {
+    let uuid = file.strip_suffix(".jpg").ok_or(Status::NotFound)?;
+
+    let song = Song::get_song(QueueParam::Id(uuid.to_string()), pool).await
+        .map_err(|_| Status::InternalServerError)?
+        .filter(|song| !song.cover_url.is_empty())
+        .ok_or(Status::NotFound)?;
+
+    let size = size.map(CoverCache::snap_size);
+
+    // Cached files never change for a given url and size, so those make a stable ETag
+    let etag = format!(
+        "\"{}-{}\"",
+        CoverCache::key(&song.cover_url),
+        size.map_or_else(|| "original".to_string(), |size| size.to_string())
+    );
+
+    if if_none_match.0.as_deref() == Some(etag.as_str()) {
+        return Ok(CoverResponse::NotModified { etag });
+    }
+
+    let result = match size {
+        Some(size) => cache.get_resized(&song.cover_url, size).await,
+        None => cache.get_original(&song.cover_url).await,
+    };
+
+    let bytes = result.map_err(|err| {
+        eprintln!("Error fetching cover for {}: {}", uuid, err);
+        Status::BadGateway
+    })?;
+
+    Ok(CoverResponse::Image { bytes, etag })
+}
+
+#[get("/overlay/<name>")]
+async fn get_overlay(pool: &State<Pool<MySql>>, name: String) -> Result<RawHtml<String>, Status> {
+    let uuid = Usage::get_uuid(QueueParam::Name(name), pool).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let song = Song::get_song(QueueParam::Id(uuid.clone()), pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+    let settings = ChannelSettings::get_settings(uuid.clone(), pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+
+    let layout = ChannelSettings::value_or(&settings.overlay_layout, "horizontal");
+    let animation = ChannelSettings::value_or(&settings.overlay_animation, "fade");
+    let font = ChannelSettings::value_or(&settings.overlay_font, "Inter");
+    let text_color = ChannelSettings::value_or(&settings.overlay_text_color, "#ffffff");
+    let background_color = ChannelSettings::value_or(
+        &settings.overlay_background_color,
+        "#000000b3"
+    );
+    let accent_color = ChannelSettings::value_or(&settings.overlay_accent_color, "#1db954");
+
+    // The page still renders when idle so it can pick up the next song
+    let song = song.filter(|song| {
+        !matches!(song.state(settings.stale_after_ms()), PlaybackState::Stale)
+    });
+    let hidden = if song.is_none() { " empty" } else { "" };
+    let song = song.unwrap_or_else(|| Song::empty(uuid.clone(), String::new()));
+
+    let title = song.title.clone().unwrap_or_else(|| song.song.clone());
+    let artist = song.artist.clone().unwrap_or_default();
+    let requester = song.requester.clone().unwrap_or_default();
+    let cover = if song.cover_url.is_empty() {
+        String::new()
+    } else {
+        format!("/v2/cover/{}.jpg?size=300", uuid)
+    };
+
+    // JSON is valid JS, escaping `<` keeps a stray "</script>" from ending the block
+    let uuid_js = serde_json::to_string(&uuid).unwrap_or_default().replace('<', "\\u003c");
+
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Songify Overlay</title>
+<style>
+:root {{
+    --text: {text_color};
+    --background: {background_color};
+    --accent: {accent_color};
+}}
+body {{ margin: 0; background: transparent; font-family: "{font}", sans-serif; color: var(--text); }}
+#overlay {{ display: inline-flex; align-items: center; gap: 12px; padding: 12px; border-radius: 8px; background: var(--background); border-left: 4px solid var(--accent); }}
+#overlay.vertical {{ flex-direction: column; align-items: flex-start; }}
+#overlay.text #cover {{ display: none; }}
+#overlay.empty {{ visibility: hidden; }}
+#cover {{ width: 80px; height: 80px; border-radius: 4px; object-fit: cover; }}
+#cover[src=""] {{ display: none; }}
+#title {{ font-size: 1.3em; font-weight: bold; }}
+#artist {{ opacity: 0.85; }}
+#requester {{ font-size: 0.85em; color: var(--accent); }}
+#requester:empty {{ display: none; }}
+.fade {{ animation: fade 0.6s ease-out; }}
+.slide {{ animation: slide 0.6s ease-out; }}
+@keyframes fade {{ from {{ opacity: 0; }} to {{ opacity: 1; }} }}
+@keyframes slide {{ from {{ transform: translateX(-110%); }} to {{ transform: translateX(0); }} }}
+</style>
+</head>
+<body>
+<div id="overlay" class="{layout} {animation}{hidden}">
+    <img id="cover" src="{cover}" alt="">
+    <div>
+        <div id="title">{title}</div>
+        <div id="artist">{artist}</div>
+        <div id="requester">{requester}</div>
+    </div>
+</div>
+<script>
+const uuid = {uuid_js};
+const animation = "{animation}";
+const overlay = document.getElementById("overlay");
+
+function update(song, version) {{
+    if (!song) {{
+        overlay.classList.add("empty");
+        return;
+    }}
+    overlay.classList.remove("empty");
+
+    document.getElementById("title").textContent = song.title || song.song;
+    document.getElementById("artist").textContent = song.artist || "";
+    document.getElementById("requester").textContent = song.requester || "";
+    document.getElementById("cover").src = song.cover_url
+        ? "/v2/cover/" + encodeURIComponent(uuid) + ".jpg?size=300&v=" + encodeURIComponent(version)
+        : "";
+
+    // Restart the entry animation for the new song
+    overlay.classList.remove(animation);
+    void overlay.offsetWidth;
+    overlay.classList.add(animation);
+}}
+
+const source = new EventSource("/v2/song/stream?uuid=" + encodeURIComponent(uuid));
+source.addEventListener("song", (event) => update(JSON.parse(event.data), event.lastEventId));
+
+// No events arrive once the desktop app is closed, so check for a stale song now and then
+setInterval(() => {{
+    fetch("/v2/getsong?full=true&uuid=" + encodeURIComponent(uuid))
+        .then((response) => response.json())
+        .then((song) => overlay.classList.toggle("empty", song.state === "stale" || song.state === "none"))
+        .catch(() => {{}});
+}}, 60000);
+</script>
+</body>
+</html>"#,
+        title = escape_html(&title),
+        artist = escape_html(&artist),
+        requester = escape_html(&requester),
+        cover = escape_html(&cover)
+    );
+
+    Ok(RawHtml(page))
+}
+
+#[get("/song/stream?<params..>")]
+async fn song_stream(
+    pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
+    params: QueueParams,
+    last_event_id: LastEventId,
+    mut end: Shutdown
+) -> Result<EventStream![], Status> {
+    let param = if let Some(uuid) = params.uuid {
+        QueueParam::Id(uuid)
+    } else if let Some(name) = params.name {
+        QueueParam::Name(name)
+    } else {
+        return Err(Status::BadRequest);
+    };
+
+    let uuid = Usage::get_uuid(param, pool).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    // Subscribe before reading the current song so no update can slip in between
+    let mut rx = events.subscribe();
+    let song = Song::get_song(QueueParam::Id(uuid.clone()), pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+
+    // A reconnecting client that already saw the latest event doesn't need it again
+    let boot = events.boot;
+    let latest_id = events.latest_song_id(&uuid);
+    let resumed = match (&latest_id, &last_event_id.0) {
+        (Some(latest), Some(last)) => latest == last,
+        _ => false,
+    };
+
+    let stream =
+        EventStream! {
+        yield Event::retry(Duration::from_secs(5));
+
+        if !resumed {
+            let event = Event::json(&song).event("song");
+            yield match latest_id {
+                Some(id) => event.id(id),
+                None => event,
+            };
+        }
+
+        loop {
+            let msg = select! {
+                msg = rx.recv() => match msg {
+                    Ok(msg) => msg,
+                    // After missing updates, end the stream so the client
+                    // reconnects and starts over from a fresh snapshot
+                    Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => break,
+                },
+                _ = &mut end => break,
+            };
+
+            if msg.uuid != uuid {
+                continue;
+            }
+
+            if let ChannelUpdate::Song { song } = msg.update {
+                yield Event::json(&song).event("song").id(format!("{}-{}", boot, msg.id));
+            }
+        }
+    };
+
+    Ok(stream.heartbeat(Duration::from_secs(15)))
+}
+
+#[get("/ws?<params..>")]
+async fn overlay_socket(
+    pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
+    params: QueueParams,
+    shutdown: Shutdown
+) -> Result<OverlaySocket, Status> {
+    let param = if let Some(uuid) = params.uuid {
+        QueueParam::Id(uuid)
+    } else if let Some(name) = params.name {
+        QueueParam::Name(name)
+    } else {
+        return Err(Status::BadRequest);
+    };
+
+    let uuid = Usage::get_uuid(param, pool).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    // Subscribe before building the snapshot so no delta can slip in between
+    let updates = events.subscribe();
+    let song = Song::get_song(QueueParam::Id(uuid.clone()), pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+    let queue = QueueSong::get_queue(QueueParam::Id(uuid.clone()), pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+
+    let snapshot = json!({
+        "type": "snapshot",
+        "song": song,
+        "queue": queue,
+    });
+
+    Ok(OverlaySocket {
+        uuid,
+        snapshot: snapshot.to_string(),
+        updates,
+        shutdown,
+    })
+}
+
+#[get("/queue?<params..>")]
+async fn get_queue(
+    pool: &State<Pool<MySql>>,
+    params: QueueParams
+) -> Result<Versioned<Json<Value>>, Status> {
+    let param = if let Some(uuid) = params.uuid {
+        QueueParam::Id(uuid)
+    } else if let Some(name) = params.name {
+        QueueParam::Name(name)
+    } else {
+        return Err(Status::BadRequest);
+    };
+
+    // Read before the queue, so a change in between leaves the client with a stale ETag
+    // rather than a current ETag on stale data
+    let version = match
+        Usage::get_uuid(param.clone(), pool).await.map_err(|_| Status::InternalServerError)?
+    {
+        Some(uuid) =>
+            QueueSong::get_version(&uuid, pool).await.map_err(|_| Status::InternalServerError)?,
+        None => 0,
+    };
+
+    let queue = QueueSong::get_queue(param.clone(), pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+
+    if !params.eta.unwrap_or(false) {
+        return Ok(Versioned { inner: Json(json!(queue)), version });
+    }
+
+    // Each item starts once the current song and everything ahead of it has played
+    let remaining_ms = match
+        Song::get_song(param, pool).await.map_err(|_| Status::InternalServerError)?
+    {
+        Some(song) => {
+            let settings = ChannelSettings::get_settings(song.uuid.clone(), pool).await.map_err(
+                |_| Status::InternalServerError
+            )?;
+            song.remaining_ms(song.state(settings.stale_after_ms()))
+        }
+        None => 0,
+    };
+
+    let now = now_millis();
+    let mut offset = remaining_ms;
+    let mut unknown_lengths = 0;
+    let items: Vec<Value> = queue
+        .into_iter()
+        .map(|song| {
+            let mut item = json!(song);
+            item["starts_in_ms"] = json!(offset);
+            item["starts_at"] = json!(now.saturating_add(offset));
+
+            // Lengths are parsed from client input, so huge values saturate instead of wrapping
+            match song.LengthMs {
+                Some(length) => {
+                    offset = offset.saturating_add(length);
+                }
+                None => {
+                    unknown_lengths += 1;
+                }
+            }
+
+            item
+        })
+        .collect();
+
+    // Items without a length count as 0, so `unknown_lengths` > 0 means later estimates are early
+    Ok(Versioned {
+        inner: Json(
+            json!({
+        "queue": items,
+        "current_remaining_ms": remaining_ms,
+        "total_duration_ms": offset - remaining_ms,
+        "unknown_lengths": unknown_lengths,
+    })
+        ),
+        version,
+    })
+}
+
+#[get("/queue/export?<params..>")]
+async fn export_queue(
+    pool: &State<Pool<MySql>>,
+    params: QueueExportParams
+) -> Result<(ContentType, String), Status> {
+    let param = if let Some(uuid) = params.uuid {
+        QueueParam::Id(uuid)
+    } else if let Some(name) = params.name {
+        QueueParam::Name(name)
+    } else {
+        return Err(Status::BadRequest);
+    };
+
+    let queue = QueueSong::get_queue(param, pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+
+    match params.filetype.as_deref().map(str::to_lowercase).as_deref() {
+        None | Some("json") =>
+            Ok((
+                ContentType::JSON,
+                json!(queue).to_string(),
+            )),
+        Some("csv") => Ok((ContentType::CSV, queue_to_csv(&queue))),
+        _ => Err(Status::BadRequest),
+    }
+}
+
+#[post("/queue/import?<api_key>", format = "json", data = "<payload>")]
+async fn import_queue(
+    pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
+    api_key: &str,
+    payload: Json<QueueImportPayload>
+) -> Result<Json<Vec<QueueSong>>, QueueError> {
+    let payload = payload.into_inner();
+    verify_access_key(&payload.uuid, api_key, pool).await?;
+
+    let uuid = payload.uuid.clone();
+    let items = QueueSong::import_queue(payload.uuid, payload.items, pool).await?;
+
+    for item in &items {
+        events.publish(uuid.clone(), ChannelUpdate::QueueAdded { item: item.clone() });
+    }
+
+    Ok(Json(items))
+}
+
+#[get("/queue_history?<params..>")]
+async fn get_queue_history(
+    pool: &State<Pool<MySql>>,
+    params: QueueHistoryParams
+) -> Result<Json<Vec<QueueSong>>, Status> {
+    let status = match params.status {
+        Some(status) => Some(QueueStatus::parse(&status).ok_or(Status::BadRequest)?),
+        None => None,
+    };
+
+    QueueSong::get_queue_history(
+        params.uuid,
+        status,
+        params.limit.unwrap_or(100).min(1000),
+        pool
+    ).await.map_or(Err(Status::InternalServerError), |queue| Ok(Json(queue)))
+}
+
+#[post("/queue?<api_key>", format = "json", data = "<song>")]
+async fn add_to_queue(
+    pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
+    api_key: &str,
+    if_match: IfMatch,
+    song: Json<QueuePostPayload>
+) -> Result<Versioned<Json<QueueSong>>, QueueError> {
+    let song = song.into_inner();
+    verify_access_key(&song.uuid, api_key, pool).await?;
+
+    let uuid = song.uuid.clone();
+    let mut item = song.queueItem;
+    item.Priority = song.priority.or(item.Priority);
+
+    let (item, version) = QueueSong::add_to_queue(song.uuid, item, &if_match, pool).await?;
+    events.publish(uuid, ChannelUpdate::QueueAdded { item: item.clone() });
+
+    Ok(Versioned { inner: Json(item), version })
+}
+
+#[patch("/queue?<api_key>", format = "json", data = "<song>")]
+async fn set_queue_song_played(
+    pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
+    api_key: &str,
+    if_match: IfMatch,
+    song: Json<QueueUpdatePayload>
+) -> Result<Versioned<()>, Status> {
+    let song = song.into_inner();
+    verify_access_key(&song.uuid, api_key, pool).await?;
+
+    let status = song.status.unwrap_or(QueueStatus::Played);
+    if status == QueueStatus::Queued {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let actor = song.actor.unwrap_or_else(|| "client".to_string());
+
+    let version = match
+        QueueSong::remove_from_queue(
+            song.uuid.clone(),
+            song.queueid,
+            status,
+            actor,
+            &if_match,
+            pool
+        ).await
+    {
+        Ok(Some(version)) => version,
+        Ok(None) => {
+            return Err(Status::PreconditionFailed);
+        }
+        Err(_) => {
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    events.publish(song.uuid, ChannelUpdate::QueuePlayed { queueid: song.queueid, status });
+
+    Ok(Versioned { inner: (), version })
+}
+
+#[patch("/queue/move?<api_key>", format = "json", data = "<payload>")]
+async fn move_queue_song(
+    pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
+    api_key: &str,
+    payload: Json<QueueMovePayload>
+) -> Result<Json<Vec<QueueSong>>, Status> {
+    let payload = payload.into_inner();
+    verify_access_key(&payload.uuid, api_key, pool).await?;
+
+    let position = QueueSong::move_in_queue(
+        payload.uuid.clone(),
+        payload.queueid,
+        payload.position,
+        pool
+    ).await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    events.publish(payload.uuid.clone(), ChannelUpdate::QueueMoved {
+        queueid: payload.queueid,
+        position,
+    });
+
+    QueueSong::get_queue(QueueParam::Id(payload.uuid), pool).await.map_or(
+        Err(Status::InternalServerError),
+        |queue| Ok(Json(queue))
+    )
+}
+
+#[post("/queue_delete?<api_key>", format = "json", data = "<queue>")]
+async fn clear_queue(
+    pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
+    api_key: &str,
+    if_match: IfMatch,
+    queue: Json<QueueClearPayload>
+) -> Result<Versioned<()>, Status> {
+    let queue = queue.into_inner();
+    verify_access_key(&queue.uuid, api_key, pool).await?;
+
+    let actor = queue.actor.unwrap_or_else(|| "client".to_string());
+
+    let version = match QueueSong::clear_queue(queue.uuid.clone(), actor, &if_match, pool).await {
+        Ok(Some(version)) => version,
+        Ok(None) => {
+            return Err(Status::PreconditionFailed);
+        }
+        Err(_) => {
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    events.publish(queue.uuid, ChannelUpdate::QueueCleared);
+
+    Ok(Versioned { inner: (), version })
+}
+
+#[post("/queue_restore?<api_key>", format = "json", data = "<payload>")]
+async fn restore_queue(
+    pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
     api_key: &str,
-    pool: &State<Pool<MySql>>
-) -> Result<(), Status> {
-    let access_key = Usage::get_access_key(uuid.to_string(), pool).await.map_err(
-        |_| Status::InternalServerError
-    )?;
+    payload: Json<QueueRestorePayload>
+) -> Result<Json<Vec<QueueSong>>, Status> {
+    let payload = payload.into_inner();
+    verify_access_key(&payload.uuid, api_key, pool).await?;
 
-    match access_key {
-        Some(key) => {
-            if key != api_key {
-                println!("Access key mismatch");
-                println!("{} != {}", key, api_key);
-                return Err(Status::Unauthorized);
-            }
+    let actor = payload.actor.unwrap_or_else(|| "client".to_string());
+
+    let items = match QueueSong::restore_queue(payload.uuid.clone(), actor, pool).await {
+        Ok(Some(items)) => items,
+        Ok(None) => {
+            return Err(Status::NotFound);
         }
-        None => {
-            Usage::set_access_key(uuid.to_string(), api_key.to_string(), pool).await.map_err(
-                |_| Status::InternalServerError
-            )?;
+        Err(_) => {
+            return Err(Status::InternalServerError);
         }
-    }
+    };
 
-    Ok(())
+    events.publish(payload.uuid, ChannelUpdate::QueueRestored { items: items.clone() });
+
+    Ok(Json(items))
 }
 
-enum SongResponse {
-    Json(Json<Value>),
-    Plain(RawText<String>),
+#[get("/blocklist?<uuid>&<api_key>")]
+async fn get_blocklist(
+    pool: &State<Pool<MySql>>,
+    uuid: String,
+    api_key: &str
+) -> Result<Json<Vec<BlocklistEntry>>, Status> {
+    verify_access_key(&uuid, api_key, pool).await?;
+
+    BlocklistEntry::get_entries(&uuid, pool).await.map_or(
+        Err(Status::InternalServerError),
+        |entries| Ok(Json(entries))
+    )
 }
 
-impl<'r> Responder<'r, 'static> for SongResponse {
-    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
-        match self {
-            SongResponse::Json(json) => json.respond_to(req),
-            SongResponse::Plain(text) => text.respond_to(req),
-        }
+#[post("/blocklist?<api_key>", format = "json", data = "<payload>")]
+async fn add_blocklist_entry(
+    pool: &State<Pool<MySql>>,
+    api_key: &str,
+    payload: Json<BlocklistPayload>
+) -> Result<Json<BlocklistEntry>, Status> {
+    let payload = payload.into_inner();
+    verify_access_key(&payload.uuid, api_key, pool).await?;
+
+    if payload.value.trim().is_empty() || payload.value.len() > 255 {
+        return Err(Status::UnprocessableEntity);
     }
-}
 
-#[get("/getsong?<params..>")]
-async fn get_song(pool: &State<Pool<MySql>>, params: QueueParams) -> Result<SongResponse, Status> {
-    let param = if let Some(uuid) = params.uuid {
-        QueueParam::Id(uuid)
-    } else if let Some(name) = params.name {
-        QueueParam::Name(name)
-    } else {
-        return Err(Status::BadRequest);
-    };
+    match BlocklistEntry::add_entry(payload, pool).await {
+        Ok(Some(entry)) => Ok(Json(entry)),
+        Ok(None) => Err(Status::Conflict),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
 
-    let song = Song::get_song(param, pool).await.map_err(|_| Status::InternalServerError)?;
+#[delete("/blocklist/<id>?<uuid>&<api_key>")]
+async fn delete_blocklist_entry(
+    pool: &State<Pool<MySql>>,
+    id: u64,
+    uuid: String,
+    api_key: &str
+) -> Result<(), Status> {
+    verify_access_key(&uuid, api_key, pool).await?;
 
-    if params.full.unwrap_or(false) {
-        Ok(SongResponse::Json(Json(json!(song))))
-    } else {
-        Ok(SongResponse::Plain(RawText(song.song)))
+    match BlocklistEntry::delete_entry(&uuid, id, pool).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
     }
 }
 
-#[get("/getcover?<params..>")]
-async fn get_cover(pool: &State<Pool<MySql>>, params: QueueParams) -> Result<String, Status> {
-    let param = if let Some(uuid) = params.uuid {
-        QueueParam::Id(uuid)
-    } else if let Some(name) = params.name {
-        QueueParam::Name(name)
-    } else {
-        return Err(Status::BadRequest);
-    };
+#[get("/bans?<uuid>&<api_key>")]
+async fn get_bans(
+    pool: &State<Pool<MySql>>,
+    uuid: String,
+    api_key: &str
+) -> Result<Json<Vec<RequesterBan>>, Status> {
+    verify_access_key(&uuid, api_key, pool).await?;
 
-    Song::get_song(param, pool).await.map_or(Err(Status::InternalServerError), |song|
-        Ok(song.cover_url)
+    RequesterBan::get_bans(&uuid, pool).await.map_or(
+        Err(Status::InternalServerError),
+        |bans| Ok(Json(bans))
     )
 }
 
-#[get("/queue?<params..>")]
-async fn get_queue(
+#[post("/bans?<api_key>", format = "json", data = "<payload>")]
+async fn ban_requester(
     pool: &State<Pool<MySql>>,
-    params: QueueParams
-) -> Result<Json<Vec<QueueSong>>, Status> {
-    let param = if let Some(uuid) = params.uuid {
-        QueueParam::Id(uuid)
-    } else if let Some(name) = params.name {
-        QueueParam::Name(name)
-    } else {
-        return Err(Status::BadRequest);
-    };
+    api_key: &str,
+    payload: Json<RequesterBanPayload>
+) -> Result<Json<RequesterBan>, Status> {
+    let payload = payload.into_inner();
+    verify_access_key(&payload.uuid, api_key, pool).await?;
+
+    let requester = payload.requester.trim();
+    if requester.is_empty() || requester.len() > 255 {
+        return Err(Status::UnprocessableEntity);
+    }
+    if
+        payload.duration_secs.is_some_and(|secs| secs <= 0 || secs > RequesterBan::MAX_DURATION_SECS)
+    {
+        return Err(Status::UnprocessableEntity);
+    }
 
-    QueueSong::get_queue(param, pool).await.map_or(Err(Status::InternalServerError), |queue|
-        Ok(Json(queue))
+    RequesterBan::ban(payload, pool).await.map_or(
+        Err(Status::InternalServerError),
+        |ban| Ok(Json(ban))
     )
 }
 
-#[post("/queue?<api_key>", format = "json", data = "<song>")]
-async fn add_to_queue(
+#[delete("/bans/<id>?<uuid>&<api_key>&<actor>")]
+async fn unban_requester(
     pool: &State<Pool<MySql>>,
+    id: u64,
+    uuid: String,
     api_key: &str,
-    song: Json<QueuePostPayload>
-) -> Result<Json<QueueSong>, Status> {
-    let song = song.into_inner();
-    verify_access_key(&song.uuid, api_key, pool).await?;
+    actor: Option<String>
+) -> Result<(), Status> {
+    verify_access_key(&uuid, api_key, pool).await?;
 
-    QueueSong::add_to_queue(song.uuid, song.queueItem, pool).await.map_or(
+    let actor = actor.unwrap_or_else(|| "client".to_string());
+
+    match RequesterBan::unban(&uuid, id, actor, pool).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[get("/bans/audit?<uuid>&<api_key>&<limit>")]
+async fn get_ban_audit(
+    pool: &State<Pool<MySql>>,
+    uuid: String,
+    api_key: &str,
+    limit: Option<u32>
+) -> Result<Json<Vec<RequesterAudit>>, Status> {
+    verify_access_key(&uuid, api_key, pool).await?;
+
+    RequesterBan::get_audit(&uuid, limit.unwrap_or(100).min(1000), pool).await.map_or(
         Err(Status::InternalServerError),
-        |song| Ok(Json(song))
+        |audit| Ok(Json(audit))
     )
 }
 
-#[patch("/queue?<api_key>", format = "json", data = "<song>")]
-async fn set_queue_song_played(
+#[get("/webhooks?<uuid>&<api_key>")]
+async fn get_webhooks(
+    pool: &State<Pool<MySql>>,
+    uuid: String,
+    api_key: &str
+) -> Result<Json<Vec<Webhook>>, Status> {
+    verify_access_key(&uuid, api_key, pool).await?;
+
+    Webhook::get_webhooks(&uuid, pool).await.map_or(
+        Err(Status::InternalServerError),
+        |webhooks| Ok(Json(webhooks))
+    )
+}
+
+#[post("/webhooks?<api_key>", format = "json", data = "<payload>")]
+async fn add_webhook(
     pool: &State<Pool<MySql>>,
+    channels: &State<Arc<WebhookChannels>>,
     api_key: &str,
-    song: Json<QueueUpdatePayload>
-) -> Result<(), Status> {
-    let song = song.into_inner();
-    verify_access_key(&song.uuid, api_key, pool).await?;
+    payload: Json<WebhookPayload>
+) -> Result<Json<Value>, Status> {
+    let payload = payload.into_inner();
+    verify_access_key(&payload.uuid, api_key, pool).await?;
+
+    if let Err(e) = payload.validate() {
+        println!("Rejected webhook for {}: {}", payload.uuid, e);
+        return Err(Status::UnprocessableEntity);
+    }
 
-    match QueueSong::remove_from_queue(song.uuid, song.queueid, pool).await {
-        Ok(_) => (),
+    // Checked again on every delivery, DNS can change after registration
+    if let Err(e) = Webhook::client(&payload.url).await {
+        println!("Rejected webhook for {}: {}", payload.uuid, e);
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let webhook = match Webhook::add_webhook(payload, pool).await {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => {
+            return Err(Status::Conflict);
+        }
         Err(_) => {
             return Err(Status::InternalServerError);
         }
+    };
+
+    channels.refresh(&webhook.uuid, pool).await.map_err(|_| Status::InternalServerError)?;
+
+    // The secret is needed to verify signatures and isn't shown again
+    let mut value = json!(webhook);
+    value["secret"] = json!(webhook.secret);
+
+    Ok(Json(value))
+}
+
+#[delete("/webhooks/<id>?<uuid>&<api_key>")]
+async fn delete_webhook(
+    pool: &State<Pool<MySql>>,
+    channels: &State<Arc<WebhookChannels>>,
+    id: u64,
+    uuid: String,
+    api_key: &str
+) -> Result<(), Status> {
+    verify_access_key(&uuid, api_key, pool).await?;
+
+    match Webhook::delete_webhook(&uuid, id, pool).await {
+        Ok(true) => channels.refresh(&uuid, pool).await.map_err(|_| Status::InternalServerError),
+        Ok(false) => Err(Status::NotFound),
+        Err(_) => Err(Status::InternalServerError),
     }
+}
 
-    Ok(())
+#[get("/webhooks/deliveries?<uuid>&<api_key>&<limit>")]
+async fn get_webhook_deliveries(
+    pool: &State<Pool<MySql>>,
+    uuid: String,
+    api_key: &str,
+    limit: Option<u32>
+) -> Result<Json<Vec<WebhookDelivery>>, Status> {
+    verify_access_key(&uuid, api_key, pool).await?;
+
+    Webhook::get_deliveries(&uuid, limit.unwrap_or(50).min(500), pool).await.map_or(
+        Err(Status::InternalServerError),
+        |deliveries| Ok(Json(deliveries))
+    )
 }
 
-#[post("/queue_delete?<api_key>", format = "json", data = "<queue>")]
-async fn clear_queue(
+#[get("/settings?<uuid>")]
+async fn get_settings(
+    pool: &State<Pool<MySql>>,
+    uuid: String
+) -> Result<Json<ChannelSettings>, Status> {
+    ChannelSettings::get_settings(uuid, pool).await.map_or(
+        Err(Status::InternalServerError),
+        |settings| Ok(Json(settings))
+    )
+}
+
+#[patch("/settings?<api_key>", format = "json", data = "<settings>")]
+async fn set_settings(
     pool: &State<Pool<MySql>>,
     api_key: &str,
-    queue: Json<QueueClearPayload>
+    settings: Json<ChannelSettingsPayload>
 ) -> Result<(), Status> {
-    let queue = queue.into_inner();
-    verify_access_key(&queue.uuid, api_key, pool).await?;
+    let settings = settings.into_inner();
+    verify_access_key(&settings.uuid, api_key, pool).await?;
 
-    match QueueSong::clear_queue(queue.uuid, pool).await {
-        Ok(_) => (),
-        Err(_) => {
-            return Err(Status::InternalServerError);
-        }
+    if let Err(e) = settings.validate() {
+        println!("Rejected settings for {}: {}", settings.uuid, e);
+        return Err(Status::UnprocessableEntity);
     }
 
-    Ok(())
+    ChannelSettings::set_settings(settings, pool).await.map_or(
+        Err(Status::InternalServerError),
+        |_| Ok(())
+    )
 }
 
 #[post("/telemetry", format = "json", data = "<telemetry>")]
@@ -655,11 +4428,19 @@ async fn set_telemetry(
 #[post("/song?<api_key>", format = "json", data = "<song>")]
 async fn set_song(
     pool: &State<Pool<MySql>>,
+    events: &State<ChannelEvents>,
     api_key: String,
     song: Json<SongPayload>
 ) -> Result<(), Status> {
     let data = song.into_inner();
 
+    let out_of_range = |value: Option<i64>| {
+        value.is_some_and(|ms| !(0..=Song::MAX_TRACK_MS).contains(&ms))
+    };
+    if out_of_range(data.duration_ms) || out_of_range(data.position_ms) {
+        return Err(Status::UnprocessableEntity);
+    }
+
     let cover = data.cover.map_or_else(String::new, |cover| cover);
 
     let song: Song = Song {
@@ -671,10 +4452,31 @@ async fn set_song(
         artist: data.artist,
         title: data.title,
         requester: data.requester,
+        duration_ms: data.duration_ms,
+        position_ms: data.position_ms,
+        is_playing: data.is_playing,
+        updated_at: Some(now_millis()),
     };
 
     verify_access_key(&song.uuid, &data.key, pool).await?;
-    Song::set_song(song, pool).await.map_or(Err(Status::InternalServerError), |_| Ok(()))
+    if let Some(player_type) = song.player_type() {
+        log_unknown_player_type(&song.uuid, &player_type);
+    }
+
+    let (song, advanced) = Song::set_song(song, pool).await.map_err(
+        |_| Status::InternalServerError
+    )?;
+
+    if let Some(queueid) = advanced {
+        events.publish(song.uuid.clone(), ChannelUpdate::QueuePlayed {
+            queueid,
+            status: QueueStatus::Played,
+        });
+    }
+
+    events.publish(song.uuid.clone(), ChannelUpdate::Song { song });
+
+    Ok(())
 }
 
 #[post("/history?<api_key>", format = "json", data = "<payload>")]
@@ -806,6 +4608,45 @@ async fn main() -> Result<(), rocket::Error> {
             |pool| pool
         );
     let client = Client::new(); // Reqwest client for making external API calls
+
+    let cover_dir = env::var("COVER_CACHE_DIR").unwrap_or_else(|_| "cover_cache".to_string());
+    if let Err(e) = std::fs::create_dir_all(&cover_dir) {
+        println!("Could not create cover cache directory {}: {}", cover_dir, e);
+        std::process::exit(1);
+    }
+    let events = ChannelEvents::new();
+    let webhook_channels = match WebhookChannels::load(&pool).await {
+        Ok(channels) => Arc::new(channels),
+        Err(e) => {
+            println!("Could not load webhooks: {}", e);
+            std::process::exit(1);
+        }
+    };
+    task::spawn(Webhook::dispatch(events.subscribe(), webhook_channels.clone(), pool.clone()));
+
+    let pruning = pool.clone();
+    task::spawn(async move {
+        let mut timer = interval(Duration::from_secs(60 * 60));
+        loop {
+            timer.tick().await;
+            if let Err(e) = Webhook::prune_deliveries(&pruning).await {
+                eprintln!("Could not prune webhook deliveries: {}", e);
+            }
+        }
+    });
+
+    let cover_cache = CoverCache { dir: PathBuf::from(cover_dir) };
+    let evicting = cover_cache.clone();
+    task::spawn(async move {
+        let mut timer = interval(Duration::from_secs(60 * 60));
+        loop {
+            timer.tick().await;
+            if let Err(e) = evicting.evict().await {
+                eprintln!("Could not evict cover cache: {}", e);
+            }
+        }
+    });
+
     println!("running v2 :)");
 
     rocket
@@ -816,23 +4657,293 @@ async fn main() -> Result<(), rocket::Error> {
                 get_queue,
                 add_to_queue,
                 set_queue_song_played,
+                move_queue_song,
+                get_queue_history,
+                export_queue,
+                import_queue,
+                restore_queue,
                 clear_queue,
                 set_telemetry,
                 get_song,
                 set_song,
                 get_cover,
+                get_cover_image,
+                get_song_link,
+                get_songs,
+                get_overlay,
+                song_stream,
+                overlay_socket,
                 set_history,
                 get_history_data,
                 get_twitch_name,
                 motd,
                 motd_all,
-                get_canvas
+                get_canvas,
+                get_settings,
+                set_settings,
+                get_webhooks,
+                add_webhook,
+                delete_webhook,
+                get_webhook_deliveries,
+                get_blocklist,
+                add_blocklist_entry,
+                delete_blocklist_entry,
+                get_bans,
+                ban_requester,
+                unban_requester,
+                get_ban_audit
             ]
         )
         .manage(pool)
         .manage(client)
+        .manage(events)
+        .manage(webhook_channels)
+        .manage(cover_cache)
         .attach(Cors)
         .launch().await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_length_ms_accepts_minutes_and_seconds() {
+        assert_eq!(parse_length_ms("3:45"), Some(225_000));
+        assert_eq!(parse_length_ms(" 0:07 "), Some(7_000));
+    }
+
+    #[test]
+    fn parse_length_ms_accepts_hours() {
+        assert_eq!(parse_length_ms("1:02:03"), Some(3_723_000));
+    }
+
+    #[test]
+    fn parse_length_ms_accepts_raw_milliseconds() {
+        assert_eq!(parse_length_ms("215000"), Some(215_000));
+        assert_eq!(parse_length_ms("-5"), None);
+    }
+
+    #[test]
+    fn parse_length_ms_rejects_out_of_range_fields() {
+        assert_eq!(parse_length_ms("3:60"), None);
+        assert_eq!(parse_length_ms("1:60:00"), None);
+        assert_eq!(parse_length_ms("1:-1"), None);
+        assert_eq!(parse_length_ms("1:2:3:4"), None);
+        assert_eq!(parse_length_ms("abc"), None);
+    }
+
+    #[test]
+    fn parse_length_ms_rejects_overflow() {
+        assert_eq!(parse_length_ms("999999999999999:00"), None);
+        assert_eq!(parse_length_ms("9999999999999999:00:00"), None);
+        assert_eq!(parse_length_ms("153722867280912930:00"), None);
+    }
+
+    fn negotiate(header: &str) -> Option<SongFormat> {
+        SongFormat::negotiate(Some(&header.parse::<Accept>().unwrap()))
+    }
+
+    #[test]
+    fn negotiate_prefers_plain_text_on_equal_weight() {
+        assert!(matches!(negotiate("application/json, text/plain, */*"), Some(SongFormat::Plain)));
+        assert!(matches!(negotiate("application/xml, */*"), Some(SongFormat::Plain)));
+        assert!(matches!(SongFormat::negotiate(None), Some(SongFormat::Plain)));
+        assert!(matches!(negotiate("*/*"), Some(SongFormat::Plain)));
+    }
+
+    #[test]
+    fn negotiate_keeps_plain_text_for_browsers() {
+        let browser = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+        assert!(matches!(negotiate(browser), Some(SongFormat::Plain)));
+        assert!(matches!(negotiate("application/json, */*;q=0.8"), Some(SongFormat::Plain)));
+    }
+
+    #[test]
+    fn negotiate_picks_structured_formats_when_preferred() {
+        assert!(matches!(negotiate("application/json"), Some(SongFormat::Json)));
+        assert!(matches!(negotiate("application/json, text/plain;q=0.8"), Some(SongFormat::Json)));
+        assert!(matches!(negotiate("text/xml, text/plain;q=0.5"), Some(SongFormat::Xml)));
+    }
+
+    #[test]
+    fn negotiate_rejects_unsupported_types() {
+        assert!(negotiate("image/png").is_none());
+        assert!(negotiate("application/json;q=0").is_none());
+    }
+
+    fn parsed(raw: &str) -> (Option<String>, Option<String>) {
+        parse_song_string(raw, true)
+    }
+
+    fn pair(artist: &str, title: &str) -> (Option<String>, Option<String>) {
+        (Some(artist.to_string()), Some(title.to_string()))
+    }
+
+    #[test]
+    fn parse_song_string_splits_on_every_separator() {
+        for separator in SONG_SEPARATORS {
+            let raw = format!("Daft Punk{}One More Time", separator);
+            assert_eq!(parsed(&raw), pair("Daft Punk", "One More Time"));
+        }
+    }
+
+    #[test]
+    fn parse_song_string_keeps_title_groups() {
+        assert_eq!(parsed("Artist - Song (feat. Someone)"), pair("Artist", "Song (feat. Someone)"));
+        assert_eq!(parsed("Artist - Song (Live)"), pair("Artist", "Song (Live)"));
+    }
+
+    #[test]
+    fn parse_song_string_strips_noise_groups() {
+        assert_eq!(parsed("Artist - Song (Official Video)"), pair("Artist", "Song"));
+        assert_eq!(parsed("Artist - Song [Lyrics]"), pair("Artist", "Song"));
+        assert_eq!(
+            parsed("Artist - Song (feat. Someone) [Official Music Video] (HD)"),
+            pair("Artist", "Song (feat. Someone)")
+        );
+    }
+
+    #[test]
+    fn parse_song_string_leaves_unbalanced_brackets() {
+        assert_eq!(parsed("Artist - Song (Official Video"), pair("Artist", "Song (Official Video"));
+    }
+
+    #[test]
+    fn parse_song_string_without_separator_is_a_title() {
+        assert_eq!(parsed("Just A Title [Lyrics]"), (None, Some("Just A Title".to_string())));
+        assert_eq!(parsed("   "), (None, None));
+        assert_eq!(
+            parse_song_string("Artist - Song (Official Video)", false),
+            pair("Artist", "Song (Official Video)")
+        );
+    }
+
+    fn song(artist: Option<&str>, title: Option<&str>, requester: Option<&str>) -> Song {
+        let mut song = Song::empty("uuid".to_string(), "Raw Artist - Raw Title".to_string());
+        song.artist = artist.map(String::from);
+        song.title = title.map(String::from);
+        song.requester = requester.map(String::from);
+        song
+    }
+
+    #[test]
+    fn render_fills_placeholders() {
+        let song = song(Some("Artist"), Some("Title"), Some("viewer"));
+        assert_eq!(song.render("{artist} - {title}"), "Artist - Title");
+        assert_eq!(song.render("{song}"), "Raw Artist - Raw Title");
+    }
+
+    #[test]
+    fn render_uses_fallbacks_for_empty_fields() {
+        let song = song(None, Some("Title"), None);
+        assert_eq!(song.render("{artist|Unknown} - {title}"), "Unknown - Title");
+    }
+
+    #[test]
+    fn render_drops_incomplete_sections() {
+        let template = "{artist} - {title}[ (requested by {requester})]";
+        assert_eq!(
+            song(Some("Artist"), Some("Title"), Some("viewer")).render(template),
+            "Artist - Title (requested by viewer)"
+        );
+        assert_eq!(song(Some("Artist"), Some("Title"), None).render(template), "Artist - Title");
+    }
+
+    #[test]
+    fn render_falls_back_to_the_raw_song_without_a_title() {
+        assert_eq!(song(None, None, None).render("{title}"), "Raw Artist - Raw Title");
+    }
+
+    #[test]
+    fn render_keeps_unknown_placeholders_and_unterminated_sections() {
+        let song = song(Some("Artist"), Some("Title"), None);
+        assert_eq!(song.render("{artst} - {title}"), "{artst} - Title");
+        assert_eq!(song.render("{title} [{artist}"), "Title [Artist");
+    }
+
+    #[test]
+    fn wildcard_match_without_wildcards_is_exact() {
+        assert!(wildcard_match("song", "song"));
+        assert!(!wildcard_match("song", "song 2"));
+    }
+
+    #[test]
+    fn wildcard_match_handles_prefix_suffix_and_infix() {
+        assert!(wildcard_match("*remix", "song (club remix"));
+        assert!(wildcard_match("live*", "live at wembley"));
+        assert!(wildcard_match("*nightcore*", "song nightcore edit"));
+        assert!(wildcard_match("a*b*c", "a-x-b-y-c"));
+        assert!(!wildcard_match("a*b*c", "a-x-c-y-b"));
+        assert!(wildcard_match("*", ""));
+    }
+
+    #[test]
+    fn wildcard_match_does_not_reuse_overlapping_text() {
+        assert!(!wildcard_match("ab*ba", "aba"));
+        assert!(wildcard_match("ab*ba", "abba"));
+    }
+
+    #[test]
+    fn wildcard_match_handles_multibyte_text() {
+        assert!(wildcard_match("*ß*", "straße"));
+        assert!(!wildcard_match("*é", "café x"));
+    }
+
+    fn playing_song(updated_at: Option<i64>, duration_ms: Option<i64>) -> Song {
+        let mut song = Song::empty("uuid".into(), "Artist - Title".into());
+        song.is_playing = Some(true);
+        song.duration_ms = duration_ms;
+        song.position_ms = duration_ms.map(|_| 0);
+        song.updated_at = updated_at;
+        song
+    }
+
+    #[test]
+    fn state_without_updated_at_is_not_stale() {
+        let song = playing_song(None, Some(1000));
+        assert!(matches!(song.state(Some(1)), PlaybackState::Playing));
+    }
+
+    #[test]
+    fn state_without_duration_only_uses_an_explicit_ttl() {
+        let song = playing_song(Some(now_millis() - 3_600_000), None);
+        assert!(matches!(song.state(None), PlaybackState::Playing));
+        assert!(matches!(song.state(Some(60_000)), PlaybackState::Stale));
+    }
+
+    #[test]
+    fn state_with_duration_uses_the_default_ttl() {
+        let song = playing_song(Some(now_millis() - 3_600_000), Some(180_000));
+        assert!(matches!(song.state(None), PlaybackState::Stale));
+        let song = playing_song(Some(now_millis()), Some(180_000));
+        assert!(matches!(song.state(None), PlaybackState::Playing));
+    }
+
+    #[test]
+    fn state_saturates_extreme_values() {
+        let mut song = playing_song(Some(i64::MIN), Some(i64::MAX));
+        song.position_ms = Some(i64::MIN);
+        assert!(matches!(song.state(Some(i64::MAX)), PlaybackState::Playing));
+        song.duration_ms = Some(0);
+        song.position_ms = Some(0);
+        assert!(matches!(song.state(Some(1)), PlaybackState::Stale));
+    }
+
+    #[test]
+    fn escape_csv_quotes_separators() {
+        assert_eq!(escape_csv("plain"), "plain");
+        assert_eq!(escape_csv("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+
+    #[test]
+    fn escape_csv_neutralizes_formulas() {
+        assert_eq!(escape_csv("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(escape_csv("+1"), "'+1");
+        assert_eq!(escape_csv("-song"), "'-song");
+        assert_eq!(escape_csv("@user"), "'@user");
+        assert_eq!(escape_csv("\tcmd"), "'\tcmd");
+    }
+}